@@ -0,0 +1,129 @@
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+
+pub const USERS_FILE: &str = "users.csv";
+const HASH_ROUNDS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Operator,
+    Supervisor,
+}
+impl Role {
+    pub fn parse(value: &str) -> Option<Role> {
+        match value.trim().to_lowercase().as_str() {
+            "viewer" => {Some(Role::Viewer)}
+            "operator" => {Some(Role::Operator)}
+            "supervisor" => {Some(Role::Supervisor)}
+            _ => {None}
+        }
+    }
+    pub fn can_reset(&self) -> bool {
+        *self >= Role::Operator
+    }
+    pub fn can_reset_all(&self) -> bool {
+        *self >= Role::Supervisor
+    }
+    pub fn can_sync_clock(&self) -> bool {
+        *self >= Role::Supervisor
+    }
+    pub fn can_write_tags(&self) -> bool {
+        *self >= Role::Supervisor
+    }
+}
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => {write!(f, "viewer")}
+            Role::Operator => {write!(f, "operator")}
+            Role::Supervisor => {write!(f, "supervisor")}
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub username: String,
+    pub role: Role,
+}
+
+struct Account {
+    username: String,
+    role: Role,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+// Credentials file, one "username,role,salt,hash" line per user, salt and hash hex encoded
+pub struct Accounts {
+    accounts: Vec<Account>,
+}
+impl Accounts {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut accounts = vec![];
+        for line in contents.lines() {
+            let parts = line.split(",").map(str::trim).collect::<Vec<&str>>();
+            if parts.len() != 4 {  // if invalid line, skip it.
+                continue;
+            }
+            let (Some(role), Some(salt), Some(hash)) = (Role::parse(parts[1]), from_hex(parts[2]), from_hex(parts[3])) else {
+                continue;
+            };
+            accounts.push(Account {username: parts[0].to_string(), role, salt, hash});
+        }
+        Ok(Accounts {accounts})
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<Session> {
+        let account = self.accounts.iter().find(|account| account.username == username)?;
+        match hash_password(password, &account.salt) == account.hash {
+            true => {Some(Session {username: account.username.clone(), role: account.role})}
+            false => {None}
+        }
+    }
+}
+
+// appends a user to the credentials file, used by the "adduser" command
+pub fn add_user(path: impl AsRef<Path>, username: &str, role: Role, password: &str) -> Result<(), String> {
+    if username.is_empty() || username.contains(",") {
+        return Err(format!("Invalid username: {username:?}"));
+    }
+    let path = path.as_ref();
+    if let Ok(accounts) = Accounts::load(path) {
+        if accounts.accounts.iter().any(|account| account.username == username) {
+            return Err(format!("User {username} already exists in {}", path.display()));
+        }
+    }
+    let salt: [u8; 16] = rand::random();
+    let line = format!("{},{},{},{}\n", username, role, to_hex(&salt), to_hex(&hash_password(password, &salt)));
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path).map_err(|e| e.to_string())?;
+    file.write_all(line.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn hash_password(password: &str, salt: &[u8]) -> Vec<u8> {
+    let mut hash = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, HASH_ROUNDS, &mut hash);
+    hash.to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    (0..value.len()).step_by(2).map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok()).collect()
+}
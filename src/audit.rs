@@ -0,0 +1,234 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+pub const AUDIT_LOG_FILE: &str = "audit_log.csv";
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const FIELD_COUNT: usize = 10;  // sequence, timestamp, operator, system, address, tag, value, result, previous hash, hash
+
+pub type SharedAuditLog = Arc<Mutex<AuditLog>>;
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub operator: String,
+    pub system: String,
+    pub plc_address: String,
+    pub tag: String,
+    pub value: String,
+    pub result: String,
+}
+
+// Append-only log where every line carries the hash of the previous line,
+// editing or removing a line breaks the chain from that point on.
+pub struct AuditLog {
+    path: PathBuf,
+    sequence: u64,
+    last_hash: String,
+    failures: Vec<String>,  // not yet shown to the operator
+}
+impl AuditLog {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let (sequence, last_hash) = read_tail(&path)?;
+        Ok(AuditLog {path, sequence, last_hash, failures: vec![]})
+    }
+
+    pub fn shared(self) -> SharedAuditLog {
+        Arc::new(Mutex::new(self))
+    }
+
+    // Synchronous, so a cancelled caller can't leave the file and the chain in memory out of step.
+    // After a failed write the chain continues from whatever the file ends with.
+    pub fn record(&mut self, entry: AuditEntry) -> Result<(), String> {
+        let result = self.append(entry);
+        if let Err(error_message) = &result {
+            if let Ok((sequence, last_hash)) = read_tail(&self.path) {
+                self.sequence = sequence;
+                self.last_hash = last_hash;
+            }
+            self.failures.push(format!("Failed to write audit log {}: {error_message}", self.path.display()));
+        }
+        result
+    }
+
+    pub fn take_failures(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.failures)
+    }
+
+    fn append(&mut self, entry: AuditEntry) -> Result<(), String> {
+        let sequence = self.sequence + 1;
+        let body = [
+            sequence.to_string(),
+            chrono::Local::now().to_rfc3339(),
+            entry.operator,
+            entry.system,
+            entry.plc_address,
+            entry.tag,
+            entry.value,
+            entry.result,
+        ].iter().map(|field| sanitize(field)).collect::<Vec<String>>().join(",");
+        let hash = chain_hash(&self.last_hash, &body);
+        let line = format!("{},{},{}\n", body, self.last_hash, hash);
+
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(&self.path)
+            .map_err(|e| e.to_string())?;
+        file.write_all(line.as_bytes()).map_err(|e| e.to_string())?;
+        file.sync_data().map_err(|e| e.to_string())?;
+
+        self.sequence = sequence;
+        self.last_hash = hash;
+        Ok(())
+    }
+}
+
+// sequence number and hash of the last entry, the chain continues from there
+fn read_tail(path: &Path) -> Result<(u64, String), String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => {contents}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {return Ok((0, GENESIS_HASH.to_string()))}  // new log
+        Err(e) => {return Err(format!("Can't read audit log {}: {e}", path.display()))}
+    };
+    let Some(line) = contents.lines().filter(|line| !line.trim().is_empty()).last() else {
+        return Ok((0, GENESIS_HASH.to_string()));
+    };
+    let fields = line.split(",").collect::<Vec<&str>>();
+    if fields.len() != FIELD_COUNT {
+        return Err(format!("Last entry of audit log {} is malformed", path.display()));
+    }
+    let sequence = fields[0].parse().map_err(|_| format!("Invalid sequence number in audit log {}", path.display()))?;
+    Ok((sequence, fields[FIELD_COUNT - 1].to_string()))
+}
+
+// Walks the whole chain, returns a summary if intact or the first broken line
+pub fn verify(path: impl AsRef<Path>) -> Result<String, String> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut last_hash = GENESIS_HASH.to_string();
+    let mut entries = 0u64;
+    for (i, line) in contents.lines().enumerate() {
+        let line_number = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = line.split(",").collect::<Vec<&str>>();
+        if fields.len() != FIELD_COUNT {
+            return Err(format!("Line {line_number}: expected {FIELD_COUNT} fields, found {}", fields.len()));
+        }
+        if fields[0].parse::<u64>().ok() != Some(entries + 1) {
+            return Err(format!("Line {line_number}: sequence number {} out of order, expected {} (entry deleted or inserted)", fields[0], entries + 1));
+        }
+        if fields[FIELD_COUNT - 2] != last_hash {
+            return Err(format!("Line {line_number}: previous hash does not match preceding entry (entry deleted or edited)"));
+        }
+        let hash = chain_hash(&last_hash, &fields[..FIELD_COUNT - 2].join(","));
+        if fields[FIELD_COUNT - 1] != hash {
+            return Err(format!("Line {line_number}: hash mismatch (entry edited)"));
+        }
+        last_hash = hash;
+        entries += 1;
+    }
+    Ok(format!("Audit log {} intact: {} entries, last hash {}", path.display(), entries, last_hash))
+}
+
+fn chain_hash(previous_hash: &str, body: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(previous_hash.as_bytes());
+    hasher.update(b",");
+    hasher.update(body.as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// keep every entry on one line with a fixed number of fields
+fn sanitize(field: &str) -> String {
+    field.replace(",", ";").replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("resetplc_audit_{name}_{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn entry(tag: &str, value: &str) -> AuditEntry {
+        AuditEntry {
+            operator: "op".to_string(),
+            system: "Sys1".to_string(),
+            plc_address: "10.0.0.1".to_string(),
+            tag: tag.to_string(),
+            value: value.to_string(),
+            result: "OK".to_string(),
+        }
+    }
+
+    fn write_entries(path: &Path, count: usize) {
+        let mut log = AuditLog::open(path).unwrap();
+        for i in 0..count {
+            log.record(entry(&format!("B_Sys1_Tag{i}"), "true")).unwrap();
+        }
+    }
+
+    #[test]
+    fn chain_verifies_and_continues_after_reopen() {
+        let path = temp_log("reopen");
+        write_entries(&path, 2);
+        write_entries(&path, 1);
+        let summary = verify(&path).unwrap();
+        assert!(summary.contains("3 entries"), "{summary}");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn commas_and_newlines_keep_one_line_per_entry() {
+        let path = temp_log("sanitize");
+        let mut log = AuditLog::open(&path).unwrap();
+        log.record(entry("B_Sys1_Tag", "a,b\nc")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+        assert!(verify(&path).is_ok());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn edited_entry_is_detected() {
+        let path = temp_log("edited");
+        write_entries(&path, 3);
+        let contents = fs::read_to_string(&path).unwrap().replacen("B_Sys1_Tag1,true", "B_Sys1_Tag1,false", 1);
+        fs::write(&path, contents).unwrap();
+        let error_message = verify(&path).unwrap_err();
+        assert!(error_message.starts_with("Line 2: hash mismatch"), "{error_message}");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn deleted_entry_is_detected() {
+        let path = temp_log("deleted");
+        write_entries(&path, 3);
+        let contents = fs::read_to_string(&path).unwrap().lines()
+            .enumerate().filter(|(i, _)| *i != 1).map(|(_, line)| format!("{line}\n")).collect::<String>();
+        fs::write(&path, contents).unwrap();
+        let error_message = verify(&path).unwrap_err();
+        assert!(error_message.starts_with("Line 2: sequence number 3 out of order"), "{error_message}");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn failed_write_is_reported_and_chain_stays_intact() {
+        let path = temp_log("failed");
+        write_entries(&path, 1);
+        let mut log = AuditLog::open(&path).unwrap();
+        log.path = std::env::temp_dir();  // a directory can't be appended to
+        assert!(log.record(entry("B_Sys1_Tag", "true")).is_err());
+        assert_eq!(log.take_failures().len(), 1);
+        assert!(log.take_failures().is_empty());
+        log.path = path.clone();
+        log.record(entry("B_Sys1_Tag", "true")).unwrap();
+        assert!(verify(&path).is_ok());
+        let _ = fs::remove_file(&path);
+    }
+}
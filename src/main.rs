@@ -1,21 +1,55 @@
 mod systempoller;
 mod statusled;
+mod audit;
+mod accounts;
 
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use iced::{Center, Color, Element, Length, Subscription, Task};
 use iced::futures::channel::mpsc::Sender;
 use iced::Theme;
-use iced::widget::{text, column, button, row, container, stack, opaque, mouse_area, center, scrollable, Row, Column, horizontal_space, vertical_space};
+use iced::widget::{text, column, Canvas, button, row, container, stack, opaque, mouse_area, center, scrollable, text_input, text_editor, checkbox, pick_list, tooltip, Row, Column, horizontal_space, vertical_space};
 use iced::clipboard;
 use rfd::{AsyncFileDialog};
-use crate::systempoller::{SystemInfo, systempoller, Event, BackgroundMessage};
+use crate::systempoller::{SystemInfo, systempoller, Event, BackgroundMessage, BootpRequest, ControllerMode, PlcValue, SelfCheck, TagInfo, TagSpec, ValueType, READ_ONLY_BUILD};
 use crate::statusled::StatusLed;
+use crate::accounts::{Accounts, Role, Session, USERS_FILE};
+
+const AUTO_LOCK_AFTER: Duration = Duration::from_secs(300);
+const MAX_LISTED_TAGS: usize = 500;
 
 #[derive(Debug, Clone)]
 enum Message {
     Data(Event),
+    UsernameChanged(String),
+    PasswordChanged(String),
+    Login,
+    Lock,
+    Tick,
     Reset(String),
+    AcknowledgeFirstOut(String),
     ResetAll,
+    SyncClock(String),
+    SetLinkDiagnostics(bool),
+    Discover,
+    SweepRangesChanged(String),
+    StartSweep,
+    EditProposedConfig(text_editor::Action),
+    SaveProposedConfig,
+    ProposedConfigSaved(Result<Option<String>, String>),
+    SetBootp(bool),
+    SelectBootpHost(String, String),  // mac address, hostname
+    AssignBootp(String),
+    OpenTagBrowser(String),
+    TagFilterChanged(String),
+    SelectTag(String, Option<ValueType>),
+    TagNameChanged(String),
+    TagTypeSelected(ValueType),
+    ReadTag,
+    WriteValueChanged(String),
+    WriteTag,
+    ConfirmWriteTag,
+    CancelWriteTag,
     ShowPopup(PopupState),
     HidePopup,
     CopyPopupText,
@@ -30,34 +64,100 @@ enum State {
 enum PopupState {
     Hidden,
     ShowSystem(String),
+    ShowSystemAlarms(String),
     ShowAll,
     ShowError(String),
+    ShowDryRun(String),
+    ShowDiscovery(String),
+    ShowSweep,
+    ShowBootp,
+    ShowFirmware,
+    ShowTagBrowser,
 }
 
 struct RecoveryApp {
     system_map: HashMap<String, SystemInfo>,
     state: State,
-    popup_state: PopupState
+    popup_state: PopupState,
+    read_only: bool,
+    link_diagnostics: bool,
+    sweep: SweepForm,
+    bootp_enabled: bool,
+    bootp_requests: Vec<BootpRequest>,
+    bootp_selection: HashMap<String, String>,  // mac address -> hostname to assign
+    tag_browser: TagBrowser,
+    self_check: Option<SelfCheck>,  // None until the first scan
+    accounts: Result<Accounts, String>,
+    session: Option<Session>,
+    login_form: LoginForm,
+    last_activity: Instant,
+}
+
+#[derive(Default)]
+struct SweepForm {
+    ranges: String,
+    status: String,
+    proposed_config: text_editor::Content,
+}
+
+#[derive(Default)]
+struct TagBrowser {
+    system_name: String,
+    tags: Vec<TagInfo>,
+    filter: String,
+    tag_name: String,
+    value_type: Option<ValueType>,
+    write_value: String,
+    pending_write: Option<PendingWrite>,
+    status: String,
+}
+
+// A write waiting for the operator to confirm it against the value the PLC holds now
+struct PendingWrite {
+    tag_name: String,
+    value: PlcValue,
+    current: Option<Result<PlcValue, String>>,  // None while reading
+}
+
+#[derive(Default)]
+struct LoginForm {
+    username: String,
+    password: String,
+    error: Option<String>,
 }
 
 impl RecoveryApp {
-    fn new() -> (Self, Task<Message>) {
+    fn new(read_only: bool) -> (Self, Task<Message>) {
         (RecoveryApp {
             system_map: HashMap::new(),
             state: State::Loading,
             popup_state: PopupState::Hidden,
+            read_only: read_only || READ_ONLY_BUILD,
+            link_diagnostics: false,
+            sweep: SweepForm::default(),
+            bootp_enabled: false,
+            bootp_requests: vec![],
+            bootp_selection: HashMap::new(),
+            tag_browser: TagBrowser::default(),
+            self_check: None,
+            accounts: Accounts::load(USERS_FILE),
+            session: None,
+            login_form: LoginForm::default(),
+            last_activity: Instant::now(),
         }, Task::none())
     }
 
     fn view(&self) -> Element<Message> {
-        match self.state {
-            State::Loading => row!["Waiting on background thread"].into(),
-            State::Running(_) => {
+        match (&self.state, &self.session) {
+            (State::Loading, _) => row!["Waiting on background thread"].into(),
+            (State::Running(_), None) => self.login_view(),
+            (State::Running(_), Some(session)) => {
                 
                 //top row with buttons
                 let mut column = Column::new().width(Length::Fill).align_x(Center);
+                let connected = self.self_check.as_ref().is_none_or(SelfCheck::connected);
                 let load_button = button("Load config").on_press(Message::FileDialog);
-                let reset_button = match self.system_map.is_empty() {
+                let reset_button = match self.system_map.is_empty() || !session.role.can_reset_all() || !connected {
                     false => button("Reset all").on_press(Message::ResetAll),
                     true => button("Reset all")
                 };
@@ -66,12 +166,37 @@ impl RecoveryApp {
                     true => button("All hosts info")
                 };
 
-                let button_row = row![
+                let mut button_row = row![
                     load_button,
                     reset_button,
-                    host_info_button
-                ].spacing(10);
+                    host_info_button,
+                    button("Firmware").on_press(Message::ShowPopup(PopupState::ShowFirmware)),
+                    button("Discover").on_press(Message::Discover),
+                    button("Generate config").on_press(Message::ShowPopup(PopupState::ShowSweep)),
+                    match session.role.can_reset() {
+                        true => {checkbox("BOOTP server", self.bootp_enabled).on_toggle(Message::SetBootp)}
+                        false => {checkbox("BOOTP server", self.bootp_enabled)}
+                    },
+                    button(text(format!("BOOTP requests ({})", self.bootp_requests.len()))).on_press(Message::ShowPopup(PopupState::ShowBootp)),
+                    checkbox("Link diagnostics", self.link_diagnostics).on_toggle(Message::SetLinkDiagnostics),
+                    button("Lock").on_press(Message::Lock),
+                    text(format!("Logged in as {} ({})", session.username, session.role)),
+                ].spacing(10).align_y(Center);
+                if self.read_only {
+                    button_row = button_row.push(text("READ-ONLY: resets are not written").size(20).color(Color::from_rgb(1.0, 0.0, 0.0)));
+                }
                 column = column.push(button_row);
+
+                // a pulled cable would otherwise look like the whole plant is down
+                if let Some(self_check) = self.self_check.as_ref().filter(|_| !connected) {
+                    column = column.push(container(column![
+                        text("You are not connected to the PSS network").size(30).color(Color::WHITE),
+                        text(self_check.info()).color(Color::WHITE),
+                        text("Systems can't be checked from here, their status lights are off until the connection is back").color(Color::WHITE),
+                    ].align_x(Center)).width(Length::Fill).padding(10).style(|_theme| container::Style {
+                        background: Some(Color::from_rgb(0.8, 0.0, 0.0).into()), ..container::Style::default()
+                    }));
+                }
                 
                 //system views
                 if self.system_map.is_empty() {
@@ -80,7 +205,7 @@ impl RecoveryApp {
                 else {
                     let mut row = Row::new();
                     for (i, system_info) in self.sorted_systems().iter().enumerate() {
-                        row = row.push(system_view(system_info));
+                        row = row.push(system_view(system_info, session.role, connected));
                         if i%5 == 4 {
                             column = column.push(row);
                             row = Row::new();
@@ -98,7 +223,7 @@ impl RecoveryApp {
                     PopupState::ShowError(error_message) => {
                         let popup = container(
                             column!(
-                                text("Error loading file:" ).size(20),
+                                text("Error:" ).size(20),
                                 text(error_message),
                                 row!(
                                     horizontal_space(),
@@ -108,17 +233,175 @@ impl RecoveryApp {
                         ).width(500).height(400).style(container::rounded_box).padding(10);
                         modal(content, popup, Message::HidePopup)
                     }
-                    _ => {  // showSystem and showAll
+                    PopupState::ShowDryRun(writes) => {
                         let popup = container(
                             column!(
-                            text("Hosts not responding:" ).size(20),
-                            scrollable(text(self.host_popup_text()).width(Length::Fill).size(15)).height(Length::Fill),
+                                text("Read-only, nothing was written:" ).size(20),
+                                scrollable(text(writes).width(Length::Fill).size(15)).height(Length::Fill),
+                                row!(
+                                    horizontal_space(),
+                                    button("OK").on_press(Message::HidePopup),
+                                )
+                            ).spacing(10)
+                        ).width(500).height(400).style(container::rounded_box).padding(10);
+                        modal(content, popup, Message::HidePopup)
+                    }
+                    PopupState::ShowBootp => {
+                        let missing_hosts = self.sorted_systems().iter()
+                            .flat_map(|system| system.missing_hosts())
+                            .collect::<Vec<String>>();
+                        let mut requests = Column::new().spacing(5);
+                        if !self.bootp_enabled {
+                            requests = requests.push(text("BOOTP server is not running"));
+                        }
+                        for request in &self.bootp_requests {
+                            let description = format!("{}  {}s ago", request.mac, request.seconds_ago);
+                            requests = requests.push(match (&request.hostname, request.address) {
+                                (Some(hostname), Some(address)) => {
+                                    row![text(format!("{description}  {hostname} -> {address}"))]
+                                }
+                                _ => {
+                                    let selected = self.bootp_selection.get(&request.mac).cloned();
+                                    let mac = request.mac.clone();
+                                    let assign_button = match (session.role.can_reset(), &selected) {
+                                        (true, Some(_)) => {button("Assign").on_press(Message::AssignBootp(request.mac.clone()))}
+                                        _ => {button("Assign")}
+                                    };
+                                    row![
+                                        text(format!("{description}  unknown")),
+                                        horizontal_space(),
+                                        pick_list(missing_hosts.clone(), selected, move |hostname| Message::SelectBootpHost(mac.clone(), hostname)),
+                                        assign_button,
+                                    ].spacing(10).align_y(Center)
+                                }
+                            });
+                        }
+                        let popup = container(
+                            column!(
+                                text("BOOTP/DHCP requests:").size(20),
+                                scrollable(requests).height(Length::Fill),
+                                row!(
+                                    horizontal_space(),
+                                    button("OK").on_press(Message::HidePopup),
+                                )
+                            ).spacing(10)
+                        ).width(700).height(400).style(container::rounded_box).padding(10);
+                        modal(content, popup, Message::HidePopup)
+                    }
+                    PopupState::ShowTagBrowser => {
+                        let browser = &self.tag_browser;
+                        let filter = browser.filter.to_lowercase();
+                        let mut tag_list = Column::new().spacing(2);
+                        for tag in browser.tags.iter().filter(|tag| tag.name.to_lowercase().contains(&filter)).take(MAX_LISTED_TAGS) {
+                            tag_list = tag_list.push(
+                                button(text(format!("{}  ({})", tag.name, tag.type_text)).size(14))
+                                    .on_press(Message::SelectTag(tag.name.clone(), tag.value_type))
+                                    .style(button::text)
+                            );
+                        }
+                        let can_write = session.role.can_write_tags() && !self.read_only;
+                        let write_button = match can_write && browser.pending_write.is_none() {
+                            true => {button("Write").on_press(Message::WriteTag)}
+                            false => {button("Write")}
+                        };
+                        let confirm_row = browser.pending_write.as_ref().map(|pending| {
+                            let current = match &pending.current {
+                                None => {"reading current value...".to_string()}
+                                Some(Ok(current)) => {format!("currently {current}")}
+                                Some(Err(error_message)) => {format!("current value unknown: {error_message}")}
+                            };
+                            let confirm_button = match pending.current {
+                                Some(Ok(_)) => {button("Confirm").on_press(Message::ConfirmWriteTag)}
+                                _ => {button("Confirm")}
+                            };
                             row!(
-                                button("Copy text").on_press(Message::CopyPopupText),
-                                horizontal_space(),
-                                button("OK").on_press(Message::HidePopup),
+                                text(format!("Write {} to {}, {current}?", pending.value, pending.tag_name)).width(Length::Fill),
+                                confirm_button,
+                                button("Cancel").on_press(Message::CancelWriteTag),
+                            ).spacing(10)
+                        });
+                        let popup = container(
+                            column!(
+                                text(format!("Tags of {}", browser.system_name)).size(20),
+                                text_input("Filter", &browser.filter).on_input(Message::TagFilterChanged),
+                                scrollable(tag_list).height(Length::Fill).width(Length::Fill),
+                                row!(
+                                    text_input("Tag name", &browser.tag_name).on_input(Message::TagNameChanged).on_submit(Message::ReadTag),
+                                    pick_list(ValueType::ALL, browser.value_type, Message::TagTypeSelected).placeholder("Type"),
+                                    button("Read").on_press(Message::ReadTag),
+                                ).spacing(10),
+                                row!(
+                                    text_input("Value to write", &browser.write_value).on_input(Message::WriteValueChanged),
+                                    write_button,
+                                ).spacing(10),
+                                Column::new().push_maybe(confirm_row),
+                                text(&browser.status),
+                                row!(
+                                    horizontal_space(),
+                                    button("Close").on_press(Message::HidePopup),
+                                )
+                            ).spacing(10)
+                        ).width(700).height(600).style(container::rounded_box).padding(10);
+                        modal(content, popup, Message::HidePopup)
+                    }
+                    PopupState::ShowSweep => {
+                        let popup = container(
+                            column!(
+                                text("Generate config from subnet sweep").size(20),
+                                row!(
+                                    text_input("Subnets, e.g. 10.1.0.0/24, 10.1.1.0/24", &self.sweep.ranges)
+                                        .on_input(Message::SweepRangesChanged).on_submit(Message::StartSweep),
+                                    button("Sweep").on_press(Message::StartSweep),
+                                ).spacing(10),
+                                text(&self.sweep.status),
+                                text_editor(&self.sweep.proposed_config).on_action(Message::EditProposedConfig).height(Length::Fill),
+                                row!(
+                                    button("Save and load").on_press(Message::SaveProposedConfig),
+                                    horizontal_space(),
+                                    button("Close").on_press(Message::HidePopup),
                                 )
                             ).spacing(10)
+                        ).width(700).height(600).style(container::rounded_box).padding(10);
+                        modal(content, popup, Message::HidePopup)
+                    }
+                    _ => {  // showSystem, showSystemAlarms, showAll, showDiscovery and showFirmware
+                        let title = match &self.popup_state {
+                            PopupState::ShowDiscovery(_) => {"Discovered devices:"}
+                            PopupState::ShowSystemAlarms(_) => {"Active alarms:"}
+                            PopupState::ShowFirmware => {"Firmware differing from the config or unknown:"}
+                            _ => {"Hosts not responding:"}
+                        };
+                        let tabs = match &self.popup_state {
+                            PopupState::ShowSystem(system_name) => {
+                                row![button("Hosts"), button("Alarms").on_press(Message::ShowPopup(PopupState::ShowSystemAlarms(system_name.to_string())))]
+                            }
+                            PopupState::ShowSystemAlarms(system_name) => {
+                                row![button("Hosts").on_press(Message::ShowPopup(PopupState::ShowSystem(system_name.to_string()))), button("Alarms")]
+                            }
+                            _ => {row![]}
+                        };
+                        let sync_clock_button = match &self.popup_state {
+                            PopupState::ShowSystem(system_name) if session.role.can_sync_clock() && !self.read_only => {
+                                Some(button("Sync clock").on_press(Message::SyncClock(system_name.to_string())))
+                            }
+                            PopupState::ShowSystem(_) => {Some(button("Sync clock"))}
+                            _ => {None}
+                        };
+                        let popup = container(
+                            column!(
+                            tabs.spacing(5),
+                            text(title).size(20),
+                            scrollable(text(self.host_popup_text()).width(Length::Fill).size(15)).height(Length::Fill),
+                            row!(button("Copy text").on_press(Message::CopyPopupText))
+                                .push_maybe(sync_clock_button)
+                            .push_maybe(match &self.popup_state {
+                                PopupState::ShowSystem(system_name) => {Some(button("Tag browser").on_press(Message::OpenTagBrowser(system_name.to_string())))}
+                                _ => {None}
+                            })
+                                .push(horizontal_space())
+                                .push(button("OK").on_press(Message::HidePopup))
+                                .spacing(10)
+                            ).spacing(10)
                         ).width(500).height(400).style(container::rounded_box).padding(10);
                         modal(content, popup, Message::HidePopup)
                     }
@@ -127,11 +410,43 @@ impl RecoveryApp {
         }
     }
 
+    fn login_view(&self) -> Element<Message> {
+        let mut form = Column::new().width(300).spacing(10);
+        form = form.push(text("PSS PLC recovery program").size(20));
+        match &self.accounts {
+            Err(error_message) => {
+                form = form.push(text(format!("No user accounts could be loaded: {error_message}")));
+                form = form.push(text("Create one with: resetplc adduser <username> <viewer|operator|supervisor>"));
+            }
+            Ok(accounts) if accounts.is_empty() => {
+                form = form.push(text(format!("No user accounts in {USERS_FILE}")));
+                form = form.push(text("Create one with: resetplc adduser <username> <viewer|operator|supervisor>"));
+            }
+            Ok(_) => {
+                form = form.push(text_input("Username", &self.login_form.username).on_input(Message::UsernameChanged).on_submit(Message::Login));
+                form = form.push(text_input("Password", &self.login_form.password).secure(true).on_input(Message::PasswordChanged).on_submit(Message::Login));
+                form = form.push(button("Log in").on_press(Message::Login));
+                if let Some(error_message) = &self.login_form.error {
+                    form = form.push(text(error_message).color(Color::from_rgb(1.0, 0.0, 0.0)));
+                }
+            }
+        }
+        center(form).into()
+    }
+
+    fn role(&self) -> Option<Role> {
+        self.session.as_ref().map(|session| session.role)
+    }
+
     fn update(&mut self, message: Message) -> Task<Message> {
+        if !matches!(message, Message::Data(_) | Message::Tick) {
+            self.last_activity = Instant::now();
+        }
         match message {
             Message::Data(event) => {
                 match event {
-                    Event::Setup(sender) => {
+                    Event::Setup(mut sender) => {
+                        let _ = sender.try_send(BackgroundMessage::SetReadOnly(self.read_only));
                         self.state = State::Running(sender);
                         Task::none()
                     }
@@ -139,33 +454,318 @@ impl RecoveryApp {
                         self.system_map.insert(system_info.name.clone(), system_info);
                         Task::none()
                     }
+                    Event::SelfCheck(self_check) => {
+                        self.self_check = Some(self_check);
+                        Task::none()
+                    }
+                    Event::AuditFailed(error_message) | Event::NotWritten(error_message) | Event::BootpFailed(error_message) => {
+                        self.popup_state = PopupState::ShowError(error_message);
+                        Task::none()
+                    }
                     Event::FileError(error_message) => {
                         self.popup_state = PopupState::ShowError(error_message);
                         Task::none()
                     }
+                    Event::Discovery(report) => {
+                        self.popup_state = PopupState::ShowDiscovery(report);
+                        Task::none()
+                    }
+                    Event::ProposedConfig(result) => {
+                        match result {
+                            Ok(proposed_config) => {
+                                self.sweep.status = "Edit the grouping if needed, then save.".to_string();
+                                self.sweep.proposed_config = text_editor::Content::with_text(&proposed_config);
+                            }
+                            Err(error_message) => {self.sweep.status = error_message}
+                        }
+                        Task::none()
+                    }
+                    Event::BootpRequests(requests) => {
+                        self.bootp_requests = requests;
+                        Task::none()
+                    }
+                    Event::TagList(system_name, result) if system_name == self.tag_browser.system_name => {
+                        match result {
+                            Ok(tags) => {
+                                self.tag_browser.status = format!("{} tags", tags.len());
+                                self.tag_browser.tags = tags;
+                            }
+                            Err(error_message) => {self.tag_browser.status = format!("Browsing failed: {error_message}")}
+                        }
+                        Task::none()
+                    }
+                    Event::TagList(_, _) => {Task::none()}
+                    Event::TagValue(tag_name, result) if self.tag_browser.pending_write.as_ref().is_some_and(|pending| pending.tag_name == tag_name && pending.current.is_none()) => {
+                        if let Some(pending) = &mut self.tag_browser.pending_write {
+                            pending.current = Some(result);
+                        }
+                        self.tag_browser.status = String::new();
+                        Task::none()
+                    }
+                    Event::TagValue(tag_name, result) => {
+                        self.tag_browser.status = match result {
+                            Ok(value) => {format!("{tag_name} = {value}")}
+                            Err(error_message) => {format!("Reading {tag_name} failed: {error_message}")}
+                        };
+                        Task::none()
+                    }
+                    Event::TagWritten(tag_name, result) => {
+                        self.tag_browser.status = match result {
+                            Ok(_) => {format!("{tag_name} written")}
+                            Err(error_message) => {format!("Writing {tag_name} failed: {error_message}")}
+                        };
+                        Task::none()
+                    }
+                    Event::DryRun(writes) => {
+                        self.popup_state = PopupState::ShowDryRun(writes.join("\n"));
+                        Task::none()
+                    }
                 }
             }
             
+            Message::UsernameChanged(username) => {
+                self.login_form.username = username;
+                Task::none()
+            }
+
+            Message::PasswordChanged(password) => {
+                self.login_form.password = password;
+                Task::none()
+            }
+
+            Message::Login => {
+                if let Ok(accounts) = &self.accounts {
+                    self.session = accounts.authenticate(&self.login_form.username, &self.login_form.password);
+                    self.login_form.error = match self.session {
+                        None => {Some("Wrong username or password".to_string())}
+                        Some(_) => {None}
+                    };
+                    self.login_form.password.clear();
+                }
+                Task::none()
+            }
+
+            Message::Lock => {
+                self.session = None;
+                self.popup_state = PopupState::Hidden;
+                Task::none()
+            }
+
+            Message::Tick => {
+                if self.session.is_some() && self.last_activity.elapsed() > AUTO_LOCK_AFTER {
+                    self.session = None;
+                    self.popup_state = PopupState::Hidden;
+                    self.login_form.error = Some("Locked after inactivity".to_string());
+                }
+                Task::none()
+            }
+
             Message::Reset(system_name) => {
-                match &mut self.state {
-                    State::Running(sender) => {
-                        let _ = sender.try_send(BackgroundMessage::Reset(system_name)).unwrap();
+                match (&mut self.state, &self.session) {
+                    (State::Running(sender), Some(session)) if session.role.can_reset() => {
+                        let _ = sender.try_send(BackgroundMessage::Reset(system_name, session.clone())).unwrap();
                         Task::none()
                     }
-                    State::Loading => {Task::none()}
+                    _ => {Task::none()}
                 }
             }
             
+            Message::AcknowledgeFirstOut(system_name) => {
+                match (&mut self.state, &self.session) {
+                    (State::Running(sender), Some(session)) if session.role.can_reset() => {
+                        let _ = sender.try_send(BackgroundMessage::AcknowledgeFirstOut(system_name, session.clone()));
+                        Task::none()
+                    }
+                    _ => {Task::none()}
+                }
+            }
+
+            Message::SyncClock(system_name) => {
+                match (&mut self.state, &self.session) {
+                    (State::Running(sender), Some(session)) if session.role.can_sync_clock() && !self.read_only => {
+                        let _ = sender.try_send(BackgroundMessage::SyncClock(system_name, session.clone())).unwrap();
+                        Task::none()
+                    }
+                    _ => {Task::none()}
+                }
+            }
+
             Message::ResetAll => {
-                match &mut self.state {
-                    State::Running(sender) => {
-                        let _ = sender.try_send(BackgroundMessage::ResetAll).unwrap();
+                match (&mut self.state, &self.session) {
+                    (State::Running(sender), Some(session)) if session.role.can_reset_all() => {
+                        let _ = sender.try_send(BackgroundMessage::ResetAll(session.clone())).unwrap();
                         Task::none()    
                     }
-                    State::Loading => {Task::none()}
+                    _ => {Task::none()}
                 }
             }
             
+            Message::SetLinkDiagnostics(value) => {
+                self.link_diagnostics = value;
+                if let State::Running(sender) = &mut self.state {
+                    let _ = sender.try_send(BackgroundMessage::SetLinkDiagnostics(value));
+                }
+                Task::none()
+            }
+
+            Message::Discover => {
+                if let State::Running(sender) = &mut self.state {
+                    let _ = sender.try_send(BackgroundMessage::Discover);
+                }
+                self.popup_state = PopupState::ShowDiscovery("Discovering devices...".to_string());
+                Task::none()
+            }
+
+            Message::SweepRangesChanged(ranges) => {
+                self.sweep.ranges = ranges;
+                Task::none()
+            }
+
+            Message::StartSweep => {
+                if let State::Running(sender) = &mut self.state {
+                    let subnets = self.sweep.ranges.split(",").map(|range| range.trim().to_string()).collect();
+                    let _ = sender.try_send(BackgroundMessage::Sweep(subnets));
+                    self.sweep.status = "Sweeping...".to_string();
+                }
+                Task::none()
+            }
+
+            Message::EditProposedConfig(action) => {
+                self.sweep.proposed_config.perform(action);
+                Task::none()
+            }
+
+            Message::SaveProposedConfig => {
+                Task::perform(save_config(self.sweep.proposed_config.text()), Message::ProposedConfigSaved)
+            }
+
+            Message::ProposedConfigSaved(result) => {
+                match result {
+                    Ok(None) => {Task::none()}  // no file was selected
+                    Ok(Some(filename)) => {
+                        self.popup_state = PopupState::Hidden;
+                        Task::done(Message::LoadConfig(Some(filename)))
+                    }
+                    Err(error_message) => {
+                        self.sweep.status = format!("Failed to save config: {error_message}");
+                        Task::none()
+                    }
+                }
+            }
+
+            Message::SetBootp(enable) => {
+                let can_enable = self.role().is_some_and(|role| role.can_reset());
+                if let (State::Running(sender), true) = (&mut self.state, can_enable) {
+                    self.bootp_enabled = enable;
+                    let _ = sender.try_send(BackgroundMessage::SetBootp(enable));
+                }
+                Task::none()
+            }
+
+            Message::SelectBootpHost(mac, hostname) => {
+                self.bootp_selection.insert(mac, hostname);
+                Task::none()
+            }
+
+            Message::AssignBootp(mac) => {
+                let can_assign = self.role().is_some_and(|role| role.can_reset());
+                if let (State::Running(sender), Some(hostname), true) = (&mut self.state, self.bootp_selection.remove(&mac), can_assign) {
+                    let _ = sender.try_send(BackgroundMessage::BootpAssign(mac, hostname));
+                }
+                Task::none()
+            }
+
+            Message::OpenTagBrowser(system_name) => {
+                self.tag_browser = TagBrowser {system_name: system_name.clone(), status: "Browsing...".to_string(), ..Default::default()};
+                self.popup_state = PopupState::ShowTagBrowser;
+                if let State::Running(sender) = &mut self.state {
+                    let _ = sender.try_send(BackgroundMessage::BrowseTags(system_name));
+                }
+                Task::none()
+            }
+
+            Message::TagFilterChanged(filter) => {
+                self.tag_browser.filter = filter;
+                Task::none()
+            }
+
+            Message::SelectTag(tag_name, value_type) => {
+                self.tag_browser.tag_name = tag_name;
+                self.tag_browser.value_type = value_type;
+                Task::none()
+            }
+
+            Message::TagNameChanged(tag_name) => {
+                self.tag_browser.tag_name = tag_name;
+                Task::none()
+            }
+
+            Message::TagTypeSelected(value_type) => {
+                self.tag_browser.value_type = Some(value_type);
+                Task::none()
+            }
+
+            Message::ReadTag => {
+                let browser = &mut self.tag_browser;
+                match (&mut self.state, browser.value_type) {
+                    (State::Running(sender), Some(value_type)) if !browser.tag_name.is_empty() => {
+                        let tag_spec = TagSpec {name: browser.tag_name.trim().to_string(), value_type};
+                        let _ = sender.try_send(BackgroundMessage::ReadTag(browser.system_name.clone(), tag_spec));
+                        browser.status = format!("Reading {}...", browser.tag_name);
+                    }
+                    _ => {browser.status = "Enter a tag name and select its type".to_string()}
+                }
+                Task::none()
+            }
+
+            Message::WriteValueChanged(value) => {
+                self.tag_browser.write_value = value;
+                Task::none()
+            }
+
+            // read the tag first, so the operator confirms the change from its current value
+            Message::WriteTag => {
+                let role = self.role();
+                let browser = &mut self.tag_browser;
+                let parsed = match browser.value_type {
+                    None => {Err("Select the tag's type".to_string())}
+                    Some(value_type) => {PlcValue::parse(value_type, &browser.write_value).map(|value| (value_type, value))}
+                };
+                match (&mut self.state, parsed) {
+                    (_, Err(error_message)) => {browser.status = error_message}
+                    _ if browser.tag_name.trim().is_empty() => {browser.status = "Enter a tag name".to_string()}
+                    (State::Running(sender), Ok((value_type, value))) if role.is_some_and(|role| role.can_write_tags()) && !self.read_only => {
+                        let tag_spec = TagSpec {name: browser.tag_name.trim().to_string(), value_type};
+                        browser.pending_write = Some(PendingWrite {tag_name: tag_spec.name.clone(), value, current: None});
+                        browser.status = String::new();
+                        let _ = sender.try_send(BackgroundMessage::ReadTag(browser.system_name.clone(), tag_spec));
+                    }
+                    _ => {browser.status = "Not allowed to write tags".to_string()}
+                }
+                Task::none()
+            }
+
+            Message::ConfirmWriteTag => {
+                let role = self.role();
+                let browser = &mut self.tag_browser;
+                let pending = browser.pending_write.take();
+                match (&mut self.state, &self.session, pending) {
+                    (State::Running(sender), Some(session), Some(pending)) if role.is_some_and(|role| role.can_write_tags()) && !self.read_only => {
+                        browser.status = format!("Writing {} to {}...", pending.value, pending.tag_name);
+                        let _ = sender.try_send(BackgroundMessage::WriteTag(browser.system_name.clone(), pending.tag_name, pending.value, session.clone()));
+                    }
+                    (_, _, None) => {}
+                    _ => {browser.status = "Not allowed to write tags".to_string()}
+                }
+                Task::none()
+            }
+
+            Message::CancelWriteTag => {
+                self.tag_browser.pending_write = None;
+                self.tag_browser.status = "Write cancelled".to_string();
+                Task::none()
+            }
+
             Message::ShowPopup(popup_state) => {
                 self.popup_state = popup_state;
                 Task::none()
@@ -187,6 +787,7 @@ impl RecoveryApp {
             Message::LoadConfig(fileoption) => {
                 match fileoption {
                     None => {}  // no file was selected
+                    Some(_) if self.role().is_none() => {}  // locked while the dialog was open
                     Some(filename) => {
                         self.system_map.clear();
                         match &mut self.state {
@@ -203,14 +804,44 @@ impl RecoveryApp {
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        Subscription::run(systempoller).map(Message::Data)
+        Subscription::batch([
+            Subscription::run(systempoller).map(Message::Data),
+            iced::time::every(Duration::from_secs(1)).map(|_| Message::Tick),
+        ])
     }
 
     fn host_popup_text(&self) -> String {
         match &self.popup_state {
             PopupState::Hidden => {"".to_string()}
             PopupState::ShowSystem(system_name) => {
-                self.system_map[system_name].failed_hosts()
+                let system_info = &self.system_map[system_name];
+                let mut popup_text = format!("{}\n\n{}", system_info.failed_hosts(), system_info.controller_info());
+                if let Some(local_address_info) = system_info.local_address_info() {
+                    popup_text = format!("{}\n{}", popup_text, local_address_info);
+                }
+                popup_text = format!("{}\n{}\n{}", popup_text, system_info.clock_info(), system_info.alarm_state_info());
+                if system_info.has_heartbeat() {
+                    popup_text = format!("{}\n{}", popup_text, system_info.heartbeat_info());
+                }
+                if system_info.is_guardlogix() {
+                    popup_text = format!("{}\n{}", popup_text, system_info.safety_info());
+                }
+                if self.link_diagnostics {
+                    popup_text = format!("{}\n\nETH links:\n{}", popup_text, system_info.link_info());
+                }
+                if system_info.has_ring() {
+                    popup_text = format!("{}\n{}", popup_text, system_info.ring_info());
+                }
+                if system_info.has_tags() {
+                    popup_text = format!("{}\n\nTags:\n{}", popup_text, system_info.tag_info());
+                }
+                if system_info.identities_ok() != Some(true) {
+                    popup_text = format!("{}\n\nIdentities not confirmed:\n{}", popup_text, system_info.identity_mismatches());
+                }
+                popup_text
+            }
+            PopupState::ShowSystemAlarms(system_name) => {
+                self.system_map[system_name].alarm_list()
             }
             PopupState::ShowAll => {
                 self.sorted_systems().iter()
@@ -219,6 +850,16 @@ impl RecoveryApp {
                     .collect::<Vec<String>>()
                     .join("\n")
             }
+            PopupState::ShowDiscovery(report) => {report.to_string()}
+            PopupState::ShowFirmware => {
+                let mismatches = self.sorted_systems().iter()
+                    .flat_map(|system| system.firmware_mismatches())
+                    .collect::<Vec<String>>();
+                match mismatches.is_empty() {
+                    true => {"All devices match the @firmware lines of the config.".to_string()}
+                    false => {mismatches.join("\n")}
+                }
+            }
             _ => {"".to_string()}
         }
     }
@@ -230,22 +871,40 @@ impl RecoveryApp {
 }
 
 // view for a system_info
-fn system_view(system_info: &SystemInfo) -> Element<Message> {
-    let labels = column![text("PLC ETHs:"), text("PLC nodes:"), text("Active alarms:")];
+// with this computer disconnected every led is off, a failed check says nothing about the system
+fn system_view(system_info: &SystemInfo, role: Role, connected: bool) -> Element<Message> {
+    let mut labels = column![text("PLC ETHs:"), text("PLC nodes:"), text("Active alarms:"), text("Controller:")];
 
-    let active_alarms_text = match system_info.active_alarms() {
+    // the reason and last known value are in the tooltip, so the operator knows what to fix
+    let active_alarms_text = tooltip(
+        text(system_info.alarm_status()),
+        container(text(system_info.alarm_state_info())).style(container::rounded_box).padding(5),
+        tooltip::Position::Bottom,
+    );
+
+    let controller_text = match system_info.controller_mode() {
         None => {"Unknown".to_string()}
-        Some(active_alarms) => {active_alarms.to_string()}
+        Some(mode) => {mode.to_string()}
     };
 
-    let values = column![text(system_info.eth_status()), text(system_info.nodes_status()), text(active_alarms_text)];
+    let nodes_text = match system_info.has_ring() {
+        true => {format!("{}  {}", system_info.nodes_status(), system_info.ring_status())}
+        false => {system_info.nodes_status()}
+    };
+
+    let mut values = column![text(system_info.eth_status()), text(nodes_text), active_alarms_text, text(controller_text)];
 
     let led_size = 20.0;
-    let eth_led = match system_info.eths_ok() {
-        true => {StatusLed::normal(led_size)}
-        false => {StatusLed::fault(led_size)}
+    let shown = |led: Canvas<StatusLed, Message>| match connected {
+        true => {led}
+        false => {StatusLed::off(led_size)}
     };
-    let nodes_leds = match system_info.nodes_ok() {
+    let eth_led = match (system_info.eths_ok(), system_info.links_ok()) {
+        (true, true) => {StatusLed::normal(led_size)}
+        (true, false) => {StatusLed::warning(led_size)}
+        (false, _) => {StatusLed::fault(led_size)}
+    };
+    let nodes_leds = match system_info.nodes_ok() && (!system_info.has_ring() || system_info.ring_ok()) {
         true => {StatusLed::normal(led_size)}
         false => {StatusLed::fault(led_size)}
     };
@@ -259,13 +918,76 @@ fn system_view(system_info: &SystemInfo) -> Element<Message> {
         }
     };
 
-    let status_boxes = column![eth_led, nodes_leds, active_alarms_led];
+    // Program and faulted get their own color, the controller answers but won't run the safety logic
+    let controller_led = match system_info.controller_mode() {
+        None => {StatusLed::fault(led_size)}
+        Some(ControllerMode::Run) | Some(ControllerMode::Online) => {StatusLed::normal(led_size)}
+        Some(ControllerMode::Program) | Some(ControllerMode::Faulted) => {StatusLed::attention(led_size)}
+        Some(ControllerMode::Unknown) => {StatusLed::warning(led_size)}
+    };
+
+    let mut status_boxes = column![shown(eth_led), shown(nodes_leds), shown(active_alarms_led), shown(controller_led)];
+
+    // the first out stays on the card after a reset cleared it in the PLC, until it is acknowledged
+    if system_info.has_first_out() {
+        let (first_out, active) = system_info.first_out();
+        labels = labels.push(text("First out:"));
+        values = values.push(text(match first_out {
+            None => {"None".to_string()}
+            Some(first_out) => {first_out.to_string()}
+        }));
+        status_boxes = status_boxes.push(shown(match (first_out, active) {
+            (Some(_), true) => {StatusLed::warning(led_size)}
+            (Some(_), false) => {StatusLed::attention(led_size)}
+            (None, _) => {StatusLed::normal(led_size)}
+        }));
+    }
+
+    // an unlocked safety task still runs, a changed signature means the safety program was changed
+    if system_info.is_guardlogix() {
+        labels = labels.push(text("Safety:"));
+        values = values.push(text(system_info.safety_status()));
+        status_boxes = status_boxes.push(shown(match system_info.safety_state() {
+            Some(state) if state.task_ok() && system_info.signature_ok() && state.locked() => {StatusLed::normal(led_size)}
+            Some(state) if state.task_ok() && system_info.signature_ok() => {StatusLed::warning(led_size)}
+            _ => {StatusLed::fault(led_size)}
+        }));
+    }
+
+    // frozen logic is a fault even though the controller still answers
+    if system_info.has_heartbeat() {
+        labels = labels.push(text("PLC logic:"));
+        values = values.push(text(system_info.heartbeat_status()));
+        status_boxes = status_boxes.push(shown(match system_info.heartbeat_frozen() {
+            Some(false) => {StatusLed::normal(led_size)}
+            Some(true) | None => {StatusLed::fault(led_size)}
+        }));
+    }
+
+    if system_info.has_identity_checks() {
+        labels = labels.push(text("Identities:"));
+        values = values.push(text(system_info.identity_status()));
+        status_boxes = status_boxes.push(shown(match system_info.identities_ok() {
+            Some(true) => {StatusLed::normal(led_size)}
+            None => {StatusLed::warning(led_size)}
+            Some(false) => {StatusLed::fault(led_size)}
+        }));
+    }
 
     let content = row!(labels, values, status_boxes).spacing(5);
 
-    let reset_button = button("Reset alarms").on_press(Message::Reset(system_info.name.clone()));
+    let reset_button = match role.can_reset() && connected {
+        true => {button("Reset alarms").on_press(Message::Reset(system_info.name.clone()))}
+        false => {button("Reset alarms")}
+    };
     let hosts_info_button = button("Hosts info").on_press(Message::ShowPopup(PopupState::ShowSystem(system_info.name.to_string())));
-    let button_row = row![reset_button, hosts_info_button].spacing(10);
+    // a first out the PLC still reports would latch again on the next scan
+    let acknowledge_button = match system_info.first_out() {
+        (Some(_), false) if role.can_reset() && connected => {Some(button("Acknowledge first out").on_press(Message::AcknowledgeFirstOut(system_info.name.clone())))}
+        (Some(_), false) => {Some(button("Acknowledge first out"))}
+        _ => {None}
+    };
+    let button_row = row![reset_button, hosts_info_button].push_maybe(acknowledge_button).spacing(10);
 
     column![text(&system_info.name).size(20), content,vertical_space().height(Length::Fixed(5.0)), button_row].align_x(Center).padding(20).into()
 }
@@ -299,11 +1021,62 @@ async fn get_filename() -> Option<String> {
     }
 }
 
+async fn save_config(contents: String) -> Result<Option<String>, String> {
+    let file = AsyncFileDialog::new()
+        .set_title("Save config file...")
+        .set_file_name("config.csv")
+        .save_file()
+        .await;
+
+    match file {
+        None => {Ok(None)}
+        Some(handle) => {
+            let filename = handle.inner().to_str().ok_or("Invalid file name")?.to_string();
+            tokio::fs::write(&filename, contents).await.map_err(|e| e.to_string())?;
+            Ok(Some(filename))
+        }
+    }
+}
+
 fn main() -> iced::Result {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("verify") {
+        let path = args.get(2).map(String::as_str).unwrap_or(audit::AUDIT_LOG_FILE);
+        match audit::verify(path) {
+            Ok(summary) => {println!("{summary}")}
+            Err(error_message) => {
+                eprintln!("Audit log verification failed: {error_message}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("adduser") {
+        let (Some(username), Some(role)) = (args.get(2), args.get(3).and_then(|role| Role::parse(role))) else {
+            eprintln!("Usage: resetplc adduser <username> <viewer|operator|supervisor>");
+            std::process::exit(1);
+        };
+        println!("Password for {username}:");
+        let mut password = String::new();
+        if std::io::stdin().read_line(&mut password).is_err() || password.trim().is_empty() {
+            eprintln!("No password given");
+            std::process::exit(1);
+        }
+        match accounts::add_user(USERS_FILE, username, role, password.trim_end_matches(['\r', '\n'])) {
+            Ok(_) => {println!("Added {username} as {role} to {USERS_FILE}")}
+            Err(error_message) => {
+                eprintln!("Failed to add user: {error_message}");
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    let read_only = args.iter().any(|arg| arg == "--read-only");
+
     iced::application("PSS PLC recovery program", RecoveryApp::update, RecoveryApp::view)
         .theme(|_| Theme::Light).centered()
         .subscription(RecoveryApp::subscription)
         .antialiasing(true)
         .window_size((1300.0, 800.0))
-        .run_with(RecoveryApp::new)
+        .run_with(move || RecoveryApp::new(read_only))
 }
@@ -6,7 +6,9 @@ use iced::{Color, Rectangle, Renderer, Theme};
 enum Status {
     Fault,
     Warning,
-    Normal
+    Attention,
+    Normal,
+    Off
 }
 
 #[derive(Debug)]
@@ -25,9 +27,15 @@ impl StatusLed {
     pub fn warning<Message>(size: f32) -> Canvas<StatusLed, Message> {
         Self::new(size, Status::Warning)
     }
+    pub fn attention<Message>(size: f32) -> Canvas<StatusLed, Message> {
+        Self::new(size, Status::Attention)
+    }
     pub fn normal<Message>(size: f32) -> Canvas<StatusLed, Message> {
         Self::new(size, Status::Normal)
     }
+    pub fn off<Message>(size: f32) -> Canvas<StatusLed, Message> {
+        Self::new(size, Status::Off)
+    }
 }
 
 impl<Message> canvas::Program<Message> for StatusLed {
@@ -51,7 +59,9 @@ impl<Message> canvas::Program<Message> for StatusLed {
         let background_color = match self.status {
             Status::Fault => {Color::from_rgb(1.0, 0.0, 0.0)}
             Status::Warning => {Color::from_rgb(1.0, 0.6471, 0.0)}
+            Status::Attention => {Color::from_rgb(0.0, 0.4, 1.0)}
             Status::Normal => {Color::from_rgb(0.0, 1.0, 0.0)}
+            Status::Off => {Color::from_rgb(0.5, 0.5, 0.5)}
         };
 
         frame.fill(&border, Color::BLACK);
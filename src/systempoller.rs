@@ -1,28 +1,71 @@
 mod multipinger;
 mod importer;
 mod plc_comms;
+mod identity;
+mod discovery;
+mod sweep;
+mod bootp;
+mod alarms;
+mod safety;
+mod selfcheck;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+use chrono::{Local, TimeDelta};
 use iced::futures::{SinkExt, Stream};
 use iced::futures::channel::mpsc;
 use iced::stream;
 use tokio::time::{sleep, Duration, Instant};
 use multipinger::{Multipinger};
 use importer::{import};
-use plc_comms::{read_and_reset};
+use plc_comms::{SessionPool, list_tags, unwritten_requests, read_ring_status, read_link_diagnostics, BackendConfig, PlcRequest, RingStatus, LinkCounters, ResetMode, UnknownReason};
+use identity::{DeviceIdentity, ExpectedFirmware, ExpectedIdentity, IdentityCheck, list_identities};
+use bootp::{BootpServer, BootpSettings, Mac, parse_mac, local_address_towards, interface_name};
+use alarms::{AlarmDefinition, AlarmState, FirstOut, FirstOutTags};
+use safety::{ExpectedSignature, SafetyState};
+pub use selfcheck::SelfCheck;
+pub use bootp::BootpRequest;
+pub use identity::ControllerMode;
+pub use plc_comms::{READ_ONLY_BUILD, PlcValue, TagInfo, TagSpec, ValueType};
+use crate::audit::{AuditEntry, AuditLog, AUDIT_LOG_FILE};
+use crate::accounts::Session;
+
+const FIRMWARE_RECHECK: Duration = Duration::from_secs(300);  // firmware only changes with a download, hosts without an @identity are asked this often
 
 #[derive(Clone, Debug)]
 pub enum Event{
     Setup(mpsc::Sender<BackgroundMessage>),
     Update(SystemInfo),
     FileError(String),
+    DryRun(Vec<String>),
+    Discovery(String),
+    ProposedConfig(Result<String, String>),
+    BootpRequests(Vec<BootpRequest>),
+    TagList(String, Result<Vec<TagInfo>, String>),  // system name, browsed tags
+    TagValue(String, Result<PlcValue, String>),  // tag name, value read
+    TagWritten(String, Result<(), String>),  // tag name, write result
+    AuditFailed(String),
+    NotWritten(String),  // requested resets and clock syncs that were dropped
+    BootpFailed(String),
+    SelfCheck(SelfCheck),
 }
 
 #[derive(Debug, Clone)]
 pub enum BackgroundMessage {
-    Reset(String),
-    ResetAll,
+    Reset(String, Session),  // system name, operator
+    ResetAll(Session),
     LoadFile(String),
+    SetReadOnly(bool),
+    SetLinkDiagnostics(bool),
+    Discover,
+    Sweep(Vec<String>),
+    SetBootp(bool),
+    BootpAssign(String, String),  // mac address, hostname
+    SyncClock(String, Session),  // system name, operator
+    AcknowledgeFirstOut(String, Session),  // system name, operator
+    BrowseTags(String),  // system name
+    ReadTag(String, TagSpec),  // system name, tag
+    WriteTag(String, String, PlcValue, Session),  // system name, tag name, value, operator
 }
 
 pub fn systempoller() -> impl Stream<Item = Event> {
@@ -30,8 +73,26 @@ pub fn systempoller() -> impl Stream<Item = Event> {
             let (sender, mut receiver) = mpsc::channel(1000);
             let _ = output.send(Event::Setup(sender)).await;
             let mut system_infos: HashMap<String, SystemInfo> = HashMap::new();
-            let mut to_reset: Vec<String> = vec![];
-            let mut pinger = Multipinger::new(vec![]);
+            let mut subnets: Vec<String> = vec![];
+            let mut bootp_settings = BootpSettings::default();
+            let mut bootp: Option<BootpServer> = None;
+            let mut bootp_assignments: Vec<(Mac, String)> = vec![];  // made in the GUI, kept when the config is reloaded
+            let mut to_reset: HashMap<String, String> = HashMap::new();  // system name -> operator
+            let mut to_sync_clock: HashMap<String, String> = HashMap::new();  // system name -> operator
+            let mut source: Option<Ipv4Addr> = None;  // local address all traffic is sent from
+            let mut references: Vec<Ipv4Addr> = vec![];  // hosts that always answer when this computer is connected
+            let mut pinger = Multipinger::new(vec![], None);
+            let mut sessions = SessionPool::new();
+            // resets are only written when they can be recorded in the audit log
+            let audit = match AuditLog::open(AUDIT_LOG_FILE) {
+                Ok(audit_log) => {Some(audit_log.shared())}
+                Err(error_message) => {
+                    let _ = output.send(Event::FileError(format!("{error_message}, resets are disabled"))).await;
+                    None
+                }
+            };
+            let mut read_only = READ_ONLY_BUILD;
+            let mut link_diagnostics = false;
 
             loop {
                 let start = Instant::now();
@@ -43,21 +104,166 @@ pub fn systempoller() -> impl Stream<Item = Event> {
                             None => {}
                             Some(message  ) => {
                                 match message {
-                                    BackgroundMessage::Reset(system_name) => {
-                                        to_reset.push(system_name);
+                                    // roles are checked again here, where the writes are recorded under the operator's name
+                                    BackgroundMessage::Reset(system_name, session) => {
+                                        match session.role.can_reset() {
+                                            true => {to_reset.insert(system_name, session.username);}
+                                            false => {
+                                                let _ = output.send(Event::NotWritten(refused(&session, &format!("reset of {system_name}")))).await;
+                                            }
+                                        }
+                                    }
+                                    BackgroundMessage::SyncClock(system_name, session) => {
+                                        match session.role.can_sync_clock() {
+                                            true => {to_sync_clock.insert(system_name, session.username);}
+                                            false => {
+                                                let _ = output.send(Event::NotWritten(refused(&session, &format!("clock sync of {system_name}")))).await;
+                                            }
+                                        }
+                                    }
+                                    BackgroundMessage::AcknowledgeFirstOut(system_name, session) => {
+                                        // only cleared once the acknowledgement is on record
+                                        let entry = system_infos.get(&system_name).and_then(|system_info| system_info.first_out_entry(&session.username, "ACKNOWLEDGED"));
+                                        match (entry, &audit) {
+                                            (None, _) => {}
+                                            (Some(_), _) if !session.role.can_reset() => {
+                                                let _ = output.send(Event::NotWritten(refused(&session, &format!("first out acknowledgement of {system_name}")))).await;
+                                            }
+                                            (Some(_), None) => {
+                                                let _ = output.send(Event::AuditFailed("audit log is not available, the first out was not acknowledged".to_string())).await;
+                                            }
+                                            (Some(entry), Some(audit)) => {
+                                                if audit.lock().await.record(entry).is_ok() {
+                                                    if let Some(system_info) = system_infos.get_mut(&system_name) {
+                                                        system_info.clear_first_out();
+                                                        let _ = output.send(Event::Update(system_info.clone())).await;
+                                                    }
+                                                }
+                                            }
+                                        }
                                     }
-                                    BackgroundMessage::ResetAll => {
-                                        to_reset.extend(system_infos.keys().cloned().collect::<Vec<_>>());
+                                    BackgroundMessage::ResetAll(session) => {
+                                        match session.role.can_reset_all() {
+                                            true => {to_reset.extend(system_infos.keys().map(|name| (name.clone(), session.username.clone())));}
+                                            false => {
+                                                let _ = output.send(Event::NotWritten(refused(&session, "reset of all systems"))).await;
+                                            }
+                                        }
+                                    }
+                                    BackgroundMessage::SetReadOnly(value) => {
+                                        read_only = value || READ_ONLY_BUILD;
+                                    }
+                                    BackgroundMessage::SetLinkDiagnostics(value) => {
+                                        link_diagnostics = value;
+                                        if !link_diagnostics {
+                                            for (_, system_info) in system_infos.iter_mut() {
+                                                system_info.clear_links();
+                                            }
+                                        }
+                                    }
+                                    BackgroundMessage::BrowseTags(system_name) => {
+                                        let request = system_infos.get(&system_name).map(|system_info| system_info.plc_request(source));
+                                        let mut output = output.clone();
+                                        tokio::spawn(async move {
+                                            let result = match request {
+                                                Some(Ok(request)) => {list_tags(request).await}
+                                                Some(Err(error_message)) => {Err(error_message)}
+                                                None => {Err(format!("unknown system {system_name}"))}
+                                            };
+                                            let _ = output.send(Event::TagList(system_name, result)).await;
+                                        });
+                                    }
+                                    BackgroundMessage::ReadTag(system_name, tag_spec) => {
+                                        let tag_name = tag_spec.name.to_string();
+                                        let result = match system_infos.get(&system_name).map(|system_info| system_info.plc_request(source)) {
+                                            Some(Ok(request)) => {sessions.read_tag(&request, tag_spec).await}
+                                            Some(Err(error_message)) => {Err(error_message)}
+                                            None => {Err(format!("unknown system {system_name}"))}
+                                        };
+                                        let _ = output.send(Event::TagValue(tag_name, result)).await;
+                                    }
+                                    BackgroundMessage::WriteTag(system_name, tag_name, value, session) => {
+                                        let result = match (system_infos.get(&system_name).map(|system_info| system_info.plc_request(source)), &audit) {
+                                            _ if read_only => {Err("read-only mode, nothing was written".to_string())}
+                                            _ if !session.role.can_write_tags() => {Err(refused(&session, &format!("write to {tag_name}")))}
+                                            (_, None) => {Err("audit log is not available, writes are disabled".to_string())}
+                                            (Some(Ok(request)), Some(audit)) => {sessions.write_tag(&request, &tag_name, value, audit, &session.username).await}
+                                            (Some(Err(error_message)), _) => {Err(error_message)}
+                                            (None, _) => {Err(format!("unknown system {system_name}"))}
+                                        };
+                                        let _ = output.send(Event::TagWritten(tag_name, result)).await;
+                                    }
+                                    BackgroundMessage::Discover => {
+                                        // compared with the config as it was when discovery started, monitoring continues meanwhile
+                                        let mut output = output.clone();
+                                        let subnets = subnets.clone();
+                                        let snapshot = system_infos.clone();
+                                        tokio::spawn(async move {
+                                            let report = match discovery::discover(&subnets, source).await {
+                                                Ok(devices) => {discovery::report(&devices, &snapshot)}
+                                                Err(error_message) => {error_message}
+                                            };
+                                            let _ = output.send(Event::Discovery(report)).await;
+                                        });
+                                    }
+                                    BackgroundMessage::Sweep(subnets) => {
+                                        // a sweep takes a while, monitoring continues meanwhile
+                                        let mut output = output.clone();
+                                        tokio::spawn(async move {
+                                            let _ = output.send(Event::ProposedConfig(sweep::propose_config(subnets, source).await)).await;
+                                        });
+                                    }
+                                    BackgroundMessage::SetBootp(enable) => {
+                                        match (enable, bootp.take()) {
+                                            (true, None) => {
+                                                match BootpServer::start(reservations(&system_infos), bootp_settings.clone()).await {
+                                                    Ok(server) => {bootp = Some(server)}
+                                                    Err(error_message) => {
+                                                        let _ = output.send(Event::FileError(error_message)).await;
+                                                    }
+                                                }
+                                            }
+                                            (true, Some(server)) => {bootp = Some(server)}
+                                            (false, Some(server)) => {
+                                                server.stop();
+                                                let _ = output.send(Event::BootpRequests(vec![])).await;
+                                            }
+                                            (false, None) => {}
+                                        }
+                                    }
+                                    BackgroundMessage::BootpAssign(mac, hostname) => {
+                                        if let (Some(server), Some(mac)) = (&bootp, parse_mac(&mac)) {
+                                            for (_, system_info) in system_infos.iter_mut() {
+                                                if let Some(address) = system_info.assign_mac(&hostname, mac) {
+                                                    server.assign(mac, hostname.clone(), address);
+                                                }
+                                            }
+                                            bootp_assignments.retain(|(assigned_mac, assigned_hostname)| *assigned_mac != mac && *assigned_hostname != hostname);
+                                            bootp_assignments.push((mac, hostname));
+                                        }
                                     }
                                     BackgroundMessage::LoadFile(filename) => {
                                         match import(&filename).await {
                                             Ok(result) => {
-                                                system_infos= result;
+                                                system_infos= result.system_infos;
+                                                subnets = result.subnets;
+                                                bootp_settings = result.bootp;
+                                                source = result.source;
+                                                references = result.references;
+                                                for (mac, hostname) in bootp_assignments.iter() {
+                                                    for system_info in system_infos.values_mut() {
+                                                        system_info.assign_mac(hostname, *mac);
+                                                    }
+                                                }
+                                                if let Some(server) = &bootp {
+                                                    server.set_reservations(reservations(&system_infos), bootp_settings.clone());
+                                                }
                                                 pinger = Multipinger::new(system_infos.values()
-                                                    .map(|sys| sys.get_addresses()).flatten().collect());
+                                                    .map(|sys| sys.get_addresses()).flatten()
+                                                    .chain(references.iter().map(|address| address.to_string())).collect(), source);
                                             }
                                             Err(error_message) => {
-                                                let _ = output.send(Event::FileError(error_message));
+                                                let _ = output.send(Event::FileError(error_message)).await;
                                             }
                                         }
 
@@ -74,33 +280,112 @@ pub fn systempoller() -> impl Stream<Item = Event> {
 
 
                     let ping_results = pinger.ping_all().await;
+                    let self_check = SelfCheck::run(source, system_infos.values().find_map(|sys| sys.get_eth_address().parse().ok()),
+                                                    &references, &ping_results).await;
+                    let _ = output.send(Event::SelfCheck(self_check)).await;
+
                     // update each system info
                     for (_, system_info) in system_infos.iter_mut() {
                         system_info.update_eth(&ping_results);
                         system_info.update_nodes(&ping_results);
                     }
 
-                    let mut plc_interactions: Vec<(String, String, bool)> = vec![];
-                    for (system_name, system_info) in system_infos.iter_mut() {
-                        if system_info.eths_ok() {  // don't try to contact plc if eth is down
-                            plc_interactions.push((system_name.to_string(), system_info.get_eth_address(), to_reset.contains(&system_name)));
+                    // only hosts with an expected identity in the config are verified
+                    let identity_results = list_identities(system_infos.values()
+                        .map(|sys| sys.get_identity_check_addresses()).flatten().collect(), source).await;
+                    for (_, system_info) in system_infos.iter_mut() {
+                        system_info.update_identities(&identity_results);
+                    }
+
+                    if link_diagnostics {
+                        let link_results = read_link_diagnostics(system_infos.values()
+                            .map(|sys| sys.get_responding_eth_addresses()).flatten().collect(), source).await;
+                        for (_, system_info) in system_infos.iter_mut() {
+                            system_info.update_links(&link_results);
                         }
-                        else {  // mark active alarms as "unknown" 
-                            system_info.alarms_active = None;
+                    }
+
+                    if let Some(server) = &bootp {
+                        let errors = server.take_errors();
+                        if !errors.is_empty() {
+                            let _ = output.send(Event::BootpFailed(errors.join("\n"))).await;
+                        }
+                        let requests = server.requests();
+                        for (_, system_info) in system_infos.iter_mut() {
+                            system_info.update_bootp(&requests);
                         }
+                        let _ = output.send(Event::BootpRequests(requests)).await;
+                    }
+
+                    let ring_results = read_ring_status(system_infos.iter()
+                        .filter_map(|(system_name, sys)| Some((system_name.to_string(), sys.get_ring_supervisor_address()?)))
+                        .collect(), source).await;
+                    for (system_name, ring) in ring_results {
+                        system_infos.get_mut(&system_name).unwrap().ring = ring;
                     }
 
-                    let plc_results = read_and_reset(plc_interactions).await;
-                    for (system_name, res) in plc_results {
-                        system_infos.get_mut(&system_name).unwrap().alarms_active = res;
+                    let mut plc_interactions: Vec<PlcRequest> = vec![];
+                    let mut not_written = vec![];
+                    for (system_name, system_info) in system_infos.iter_mut() {
+                        // only a system that answers shows the route was the right one
+                        system_info.local_address = match (source, system_info.get_responding_eth_addresses().first().and_then(|address| address.parse().ok())) {
+                            (_, None) => {None}
+                            (Some(source), Some(_)) => {Some(source)}
+                            (None, Some(eth_address)) => {local_address_towards(eth_address).await}
+                        };
+                        system_info.local_interface = system_info.local_address.and_then(interface_name);
+                        match system_info.plc_request(source) {
+                            Ok(mut request) => {
+                                request.reset = to_reset.get(system_name).cloned();
+                                request.sync_clock = to_sync_clock.get(system_name).cloned();
+                                plc_interactions.push(request);
+                            }
+                            Err(error_message) => {  // don't try to contact plc if eth is down
+                                not_written.extend(unwritten_requests(system_name, to_reset.get(system_name), to_sync_clock.get(system_name), &error_message));
+                                // mark active alarms and controller state as "unknown"
+                                system_info.alarms_active.update(Err(UnknownReason::EthDown));
+                                system_info.controller = None;
+                                system_info.tags.clear();
+                                system_info.clock_drift = None;
+                            }
+                        }
+                    }
+
+                    let scan = sessions.read_and_reset(plc_interactions, audit.clone(), read_only).await;
+                    not_written.extend(scan.not_written);
+                    for (system_name, res) in scan.statuses {
+                        let system_info = system_infos.get_mut(&system_name).unwrap();
+                        system_info.alarms_active.update(res.alarms_active);
+                        system_info.controller = res.controller;
+                        system_info.tags = res.tags;
+                        system_info.clock_drift = res.clock_drift;
+                    }
+                    for system_info in system_infos.values_mut() {
+                        system_info.update_heartbeat();
+                        system_info.update_first_out();
                     }
 
                     // Send updated clone to GUI
                     for (_, system_info) in system_infos.iter_mut() {
                         let _ = output.send(Event::Update(system_info.clone())).await;
                     }
+                    if !scan.dry_run_writes.is_empty() {
+                        let _ = output.send(Event::DryRun(scan.dry_run_writes)).await;
+                    }
+                    if !not_written.is_empty() {
+                        let _ = output.send(Event::NotWritten(not_written.join("\n"))).await;
+                    }
 
                     to_reset.clear();
+                    to_sync_clock.clear();
+                }
+
+                // writes that couldn't be recorded, from the scan, the tag browser or a detached pulse
+                if let Some(audit) = &audit {
+                    let failures = audit.lock().await.take_failures();
+                    if !failures.is_empty() {
+                        let _ = output.send(Event::AuditFailed(failures.join("\n"))).await;
+                    }
                 }
 
                 let elapsed = start.elapsed();
@@ -118,7 +403,25 @@ pub struct SystemInfo {
     pub name: String,
     plc_eths: Vec<Host>,
     plc_nodes: Vec<Host>,
-    alarms_active: Option<bool>,
+    alarms_active: AlarmState,
+    controller: Option<DeviceIdentity>,
+    ring_supervisor: Option<String>,
+    ring: Option<RingStatus>,
+    backend: BackendConfig,
+    tag_specs: Vec<TagSpec>,  // extra tags read with the sum alarm
+    tags: BTreeMap<String, PlcValue>,
+    heartbeat: Option<Heartbeat>,
+    alarm_definitions: Vec<AlarmDefinition>,
+    first_out_tags: Option<FirstOutTags>,
+    first_out: Option<FirstOut>,  // kept after a reset clears the tags, for the incident review, until acknowledged
+    first_out_active: bool,
+    reset_mode: ResetMode,
+    clock_drift: Option<TimeDelta>,
+    guardlogix: bool,
+    expected_signature: Option<ExpectedSignature>,
+    expected_firmware: Vec<ExpectedFirmware>,
+    local_address: Option<Ipv4Addr>,  // address of this computer the system answered on
+    local_interface: Option<String>,  // adapter holding that address
 }
 impl SystemInfo {
     // "backend methods
@@ -157,6 +460,384 @@ impl SystemInfo {
         addresses
     }
 
+    pub fn get_responding_eth_addresses(&self) -> Vec<String> {
+        self.plc_eths.iter().filter(|host| host.responding)
+            .map(|host| host.ip_address.to_string())
+            .collect()
+    }
+
+    pub fn update_links(&mut self, counters: &HashMap<String, Option<LinkCounters>>) {
+        for host in self.plc_eths.iter_mut() {
+            let Some(new_counters) = counters.get(&host.ip_address) else {
+                continue;
+            };
+            host.link = match (host.link.take(), new_counters) {
+                (_, None) => {None}
+                (None, Some(new_counters)) => {Some(LinkDiagnostics {counters: new_counters.clone(), errors_rising: false})}
+                (Some(previous), Some(new_counters)) => {
+                    // once rising, stay flagged until diagnostics are turned off
+                    let errors_rising = previous.errors_rising || new_counters.total_errors() > previous.counters.total_errors();
+                    Some(LinkDiagnostics {counters: new_counters.clone(), errors_rising})
+                }
+            };
+        }
+    }
+
+    pub fn clear_links(&mut self) {
+        for host in self.plc_eths.iter_mut() {
+            host.link = None;
+        }
+    }
+
+    pub fn get_identity_check_addresses(&self) -> Vec<String> {
+        let check_firmware = !self.expected_firmware.is_empty();
+        let firmware_due = |host: &Host| host.identity_read.is_none_or(|identity_read| identity_read.elapsed() >= FIRMWARE_RECHECK);
+        self.hosts().filter(|host| host.responding && (host.expected_identity.is_some() || (check_firmware && firmware_due(host))))
+            .map(|host| host.ip_address.to_string())
+            .collect()
+    }
+
+    pub fn update_identities(&mut self, identities: &HashMap<String, Option<DeviceIdentity>>) {
+        for host in self.plc_eths.iter_mut().chain(self.plc_nodes.iter_mut()) {
+            match identities.get(&host.ip_address) {
+                Some(Some(identity)) => {
+                    host.identity = Some(identity.clone());
+                    host.identity_read = Some(Instant::now());
+                }
+                Some(None) => {  // asked, wait for the recheck before asking again
+                    host.identity = None;
+                    host.identity_read = Some(Instant::now());
+                }
+                None if !host.responding => {  // the device may have been swapped by the time it answers again
+                    host.identity = None;
+                    host.identity_read = None;
+                }
+                None => {}  // firmware check not due this scan
+            }
+            let Some(expected) = &host.expected_identity else {
+                continue;
+            };
+            host.identity_check = match identities.get(&host.ip_address) {
+                None => {IdentityCheck::Unknown}  // not checked since host isn't responding
+                Some(None) => {IdentityCheck::Mismatch("no ListIdentity reply".to_string())}
+                Some(Some(identity)) => {
+                    let mismatches = expected.mismatches(identity);
+                    match mismatches.is_empty() {
+                        true => {IdentityCheck::Matches}
+                        false => {IdentityCheck::Mismatch(format!("{} ({})", identity.product_name, mismatches.join(", ")))}
+                    }
+                }
+            };
+        }
+    }
+
+    pub fn add_expected_firmware(&mut self, expected_firmware: ExpectedFirmware) {
+        self.expected_firmware.push(expected_firmware);
+    }
+
+    // controller, ETH modules and nodes whose revision differs from the one expected for their type,
+    // or that can't be checked because they didn't answer ListIdentity. Only the controller, ETH modules
+    // and nodes with an @identity are reported as unknown, other nodes may not speak EtherNet/IP at all
+    pub fn firmware_mismatches(&self) -> Vec<String> {
+        if self.expected_firmware.is_empty() {
+            return vec![];
+        }
+        let controller = ("controller", self.controller.as_ref(), true);
+        let eths = self.plc_eths.iter().map(|host| (host.hostname.as_str(), host.identity.as_ref(), true));
+        let nodes = self.plc_nodes.iter().map(|host| (host.hostname.as_str(), host.identity.as_ref(), host.expected_identity.is_some()));
+        let mut mismatches = vec![];
+        for (name, identity, must_answer) in [controller].into_iter().chain(eths).chain(nodes) {
+            let Some(identity) = identity else {
+                if must_answer {
+                    mismatches.push(format!("{} {}: unknown, no identity read", self.name, name));
+                }
+                continue;
+            };
+            let expected = self.expected_firmware.iter().filter(|expected| expected.applies_to(identity)).collect::<Vec<_>>();
+            if !expected.is_empty() && !expected.iter().any(|expected| expected.matches(identity)) {
+                let revisions = expected.iter().map(|expected| expected.revision_string()).collect::<Vec<String>>();
+                mismatches.push(format!("{} {}: {}, revision {} expected {}", self.name, name, identity.product_name,
+                                        identity.revision_string(), revisions.join(" or ")));
+            }
+        }
+        mismatches
+    }
+
+    pub fn set_backend(&mut self, backend: BackendConfig) {
+        self.backend = backend;
+    }
+
+    pub fn backend(&self) -> &BackendConfig {
+        &self.backend
+    }
+
+    pub fn alarm_definitions(&self) -> &[AlarmDefinition] {
+        &self.alarm_definitions
+    }
+
+    pub fn set_reset_mode(&mut self, reset_mode: ResetMode) {
+        self.reset_mode = reset_mode;
+    }
+
+    pub fn add_tag(&mut self, tag_spec: TagSpec) {
+        self.tag_specs.retain(|existing| existing.name != tag_spec.name);
+        self.tag_specs.push(tag_spec);
+    }
+
+    pub fn set_heartbeat(&mut self, tag_spec: TagSpec, timeout: Duration) {
+        self.heartbeat = Some(Heartbeat::new(&tag_spec.name, timeout));
+        self.add_tag(tag_spec);
+    }
+
+    fn update_heartbeat(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            heartbeat.update(self.tags.get(&heartbeat.tag));
+        }
+    }
+
+    pub fn has_heartbeat(&self) -> bool {
+        self.heartbeat.is_some()
+    }
+
+    // None until the heartbeat has been read
+    pub fn heartbeat_frozen(&self) -> Option<bool> {
+        self.heartbeat.as_ref()?.frozen()
+    }
+
+    pub fn heartbeat_status(&self) -> String {
+        match self.heartbeat_frozen() {
+            None => {"Unknown".to_string()}
+            Some(false) => {"Running".to_string()}
+            Some(true) => {"FROZEN".to_string()}
+        }
+    }
+
+    pub fn heartbeat_info(&self) -> String {
+        match &self.heartbeat {
+            None => {"".to_string()}
+            Some(heartbeat) => {heartbeat.to_string()}
+        }
+    }
+
+    // a repeated address replaces the earlier line in place, the order numbers the first-out causes
+    pub fn add_alarm(&mut self, alarm: AlarmDefinition) {
+        match self.alarm_definitions.iter_mut().find(|existing| existing.address() == alarm.address()) {
+            Some(existing) => {*existing = alarm}
+            None => {self.alarm_definitions.push(alarm)}
+        }
+    }
+
+    // request without reset or clock sync, only if the PLC can be reached
+    fn plc_request(&self, source: Option<Ipv4Addr>) -> Result<PlcRequest, String> {
+        if !self.eths_ok() {
+            return Err(format!("PLC ETH module of {} is not responding", self.name));
+        }
+        Ok(PlcRequest {
+            system_name: self.name.to_string(),
+            ip_address: self.get_eth_address(),
+            backend: self.backend.clone(),
+            tags: self.request_tags(),
+            reset: None,
+            reset_mode: self.reset_mode,
+            sync_clock: None,
+            source,
+        })
+    }
+
+    // configured tags and alarm words, each read once
+    fn request_tags(&self) -> Vec<TagSpec> {
+        let mut tags = self.tag_specs.clone();
+        let alarm_tags = self.alarm_definitions.iter().map(|alarm| alarm.tag_spec(&self.backend));
+        let first_out_tags = self.first_out_tags.iter().flat_map(|first_out_tags| first_out_tags.tag_specs(&self.backend));
+        let safety_tags = match self.guardlogix {
+            true => {SafetyState::tag_specs(&self.name)}
+            false => {vec![]}
+        };
+        for tag_spec in alarm_tags.chain(first_out_tags).chain(safety_tags) {
+            if !tags.iter().any(|existing| existing.name == tag_spec.name) {
+                tags.push(tag_spec);
+            }
+        }
+        tags
+    }
+
+    pub fn set_guardlogix(&mut self, expected_signature: Option<ExpectedSignature>) {
+        self.guardlogix = true;
+        self.expected_signature = expected_signature;
+    }
+
+    pub fn is_guardlogix(&self) -> bool {
+        self.guardlogix
+    }
+
+    pub fn safety_state(&self) -> Option<SafetyState> {
+        SafetyState::read(&self.name, &self.tags)
+    }
+
+    pub fn signature_ok(&self) -> bool {
+        match (&self.expected_signature, self.safety_state()) {
+            (None, _) => {true}
+            (Some(expected), Some(state)) => {expected.matches(&state)}
+            (Some(_), None) => {false}
+        }
+    }
+
+    pub fn safety_status(&self) -> String {
+        match self.safety_state() {
+            None => {"Unknown".to_string()}
+            Some(state) if !state.task_ok() => {state.task_status_text()}
+            Some(_) if !self.signature_ok() => {"SIGNATURE CHANGED".to_string()}
+            Some(state) if !state.locked() => {"OK, unlocked".to_string()}
+            Some(_) => {"OK, locked".to_string()}
+        }
+    }
+
+    pub fn safety_info(&self) -> String {
+        let expected = match &self.expected_signature {
+            None => {"no expected signature configured".to_string()}
+            Some(expected) => {format!("expected signature {expected}")}
+        };
+        match self.safety_state() {
+            None => {format!("Safety (tags copied by the PLC logic): not read, {expected}")}
+            Some(state) => {format!("Safety (tags copied by the PLC logic): {state}, {expected}")}
+        }
+    }
+
+    // every tag name the config gave this system
+    pub fn tag_names(&self) -> Vec<&str> {
+        let mut names = self.tag_specs.iter().map(|tag_spec| tag_spec.name.as_str()).collect::<Vec<&str>>();
+        names.extend(self.alarm_definitions.iter().map(|alarm| alarm.tag.as_str()));
+        if let Some(first_out_tags) = &self.first_out_tags {
+            names.push(&first_out_tags.cause);
+            names.extend(first_out_tags.time.as_deref());
+        }
+        names
+    }
+
+    pub fn set_first_out_tags(&mut self, first_out_tags: FirstOutTags) {
+        self.first_out_tags = Some(first_out_tags);
+    }
+
+    // latch a new first out, a cleared or unreadable one leaves the last one shown until it is acknowledged
+    fn update_first_out(&mut self) {
+        let Some(first_out_tags) = &self.first_out_tags else {
+            return;
+        };
+        let current = first_out_tags.read(&self.tags, &self.alarm_definitions);
+        self.first_out_active = current.is_some();
+        if current.is_some() && current != self.first_out {
+            self.first_out = current;
+        }
+    }
+
+    // audit record of the latched first out, so it stays on file for the incident review
+    fn first_out_entry(&self, operator: &str, result: &str) -> Option<AuditEntry> {
+        let (first_out, first_out_tags) = (self.first_out.as_ref()?, self.first_out_tags.as_ref()?);
+        Some(AuditEntry {
+            operator: operator.to_string(),
+            system: self.name.to_string(),
+            plc_address: self.get_eth_address(),
+            tag: first_out_tags.cause.to_string(),
+            value: first_out.to_string(),
+            result: result.to_string(),
+        })
+    }
+
+    fn clear_first_out(&mut self) {
+        self.first_out = None;
+    }
+
+    pub fn has_first_out(&self) -> bool {
+        self.first_out_tags.is_some()
+    }
+
+    // latched first out, and whether the controller still reports it
+    pub fn first_out(&self) -> (Option<&FirstOut>, bool) {
+        (self.first_out.as_ref(), self.first_out_active)
+    }
+
+    pub fn alarm_list(&self) -> String {
+        if self.alarm_definitions.is_empty() {
+            return format!("No alarms configured for {}, only the sum alarm is read.", self.name);
+        }
+        let mut active = vec![];
+        let mut unknown = vec![];
+        for alarm in self.alarm_definitions.iter() {
+            match alarm.active(&self.tags) {
+                Some(true) => {active.push(format!("{}  ({})", alarm.description, alarm.address()))}
+                Some(false) => {}
+                None => {unknown.push(format!("{}  ({})", alarm.description, alarm.address()))}
+            }
+        }
+        let mut lines = vec![format!("{} of {} alarms active", active.len(), self.alarm_definitions.len())];
+        if let Some(first_out) = &self.first_out {
+            lines.push(format!("First out: {first_out}"));
+        }
+        lines.extend(active);
+        if !unknown.is_empty() {
+            lines.push("\nNot read:".to_string());
+            lines.extend(unknown);
+        }
+        lines.join("\n")
+    }
+
+    pub fn has_tags(&self) -> bool {
+        !self.tag_specs.is_empty()
+    }
+
+    pub fn tag_info(&self) -> String {
+        self.tag_specs.iter()
+            .map(|tag_spec| match self.tags.get(&tag_spec.name) {
+                Some(value) => {format!("{}: {}", tag_spec.name, value)}
+                None => {format!("{}: unknown", tag_spec.name)}
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn set_ring_supervisor(&mut self, hostname: &str) -> Result<(), String> {
+        if !self.hosts().any(|host| host.hostname == hostname) {
+            return Err(format!("{hostname} is not a host of system {}", self.name));
+        }
+        self.ring_supervisor = Some(hostname.to_string());
+        Ok(())
+    }
+
+    // address of the ring supervisor, if there is one and it responds to ping
+    pub fn get_ring_supervisor_address(&self) -> Option<String> {
+        let supervisor = self.ring_supervisor.as_ref()?;
+        self.hosts().find(|host| &host.hostname == supervisor && host.responding)
+            .map(|host| host.ip_address.to_string())
+    }
+
+    pub fn update_bootp(&mut self, requests: &[BootpRequest]) {
+        for host in self.plc_eths.iter_mut().chain(self.plc_nodes.iter_mut()) {
+            host.bootp_requesting = requests.iter().any(|request| request.hostname.as_ref() == Some(&host.hostname));
+        }
+    }
+
+    // reserve the host's address for the mac, returns the address if the host belongs to this system
+    pub fn assign_mac(&mut self, hostname: &str, mac: Mac) -> Option<Ipv4Addr> {
+        let host = self.host_mut(hostname)?;
+        let address = host.ip_address.parse().ok()?;
+        host.mac = Some(mac);
+        Some(address)
+    }
+
+    pub fn missing_hosts(&self) -> Vec<String> {
+        self.hosts().filter(|host| !host.responding)
+            .map(|host| host.hostname.to_string())
+            .collect()
+    }
+
+    pub fn host_mut(&mut self, hostname: &str) -> Option<&mut Host> {
+        self.plc_eths.iter_mut().chain(self.plc_nodes.iter_mut()).find(|host| host.hostname == hostname)
+    }
+
+    fn hosts(&self) -> impl Iterator<Item = &Host> {
+        self.plc_eths.iter().chain(self.plc_nodes.iter())
+    }
+
     pub fn get_eth_address(&self) -> String {
         // return first responding eth
         for host in self.plc_eths.iter() {
@@ -178,19 +859,172 @@ impl SystemInfo {
     }
     
     pub fn active_alarms(&self) -> Option<bool> {
-        self.alarms_active
+        self.alarms_active.value()
+    }
+
+    // short reason for the card, the full one is in alarm_state_info
+    pub fn alarm_status(&self) -> String {
+        match (self.alarms_active.value(), self.alarms_active.reason()) {
+            (Some(value), _) => {value.to_string()}
+            (None, Some(reason)) => {format!("Unknown: {}", reason.short())}
+            (None, None) => {"Unknown".to_string()}
+        }
+    }
+
+    pub fn alarm_state_info(&self) -> String {
+        self.alarms_active.to_string()
     }
     
+    pub fn has_ring(&self) -> bool {
+        self.ring_supervisor.is_some()
+    }
+
+    pub fn ring_ok(&self) -> bool {
+        self.ring.as_ref().is_some_and(|ring| ring.ok())
+    }
+
+    pub fn ring_status(&self) -> String {
+        match &self.ring {
+            None => {"Ring unknown".to_string()}
+            Some(ring) if ring.ok() => {"Ring OK".to_string()}
+            Some(ring) if !ring.ring_topology => {"Not a ring".to_string()}
+            Some(ring) => {
+                match ring.last_active_nodes {
+                    [Some(port1), Some(port2)] => {
+                        format!("Ring broken between {} and {}", self.hostname_of(port1), self.hostname_of(port2))
+                    }
+                    _ => {format!("Ring broken ({})", ring.network_status_text())}
+                }
+            }
+        }
+    }
+
+    pub fn ring_info(&self) -> String {
+        match &self.ring {
+            None => {"Ring: unknown".to_string()}
+            Some(ring) => {format!("Ring: {}, status {}, {} faults since power up", self.ring_status(), ring.network_status_text(), ring.fault_count)}
+        }
+    }
+
+    fn hostname_of(&self, address: Ipv4Addr) -> String {
+        let address = address.to_string();
+        self.hosts().find(|host| host.ip_address == address)
+            .map(|host| host.hostname.to_string())
+            .unwrap_or(address)
+    }
+
+    // a Modbus panel has no mode, answering the alarm read is all there is to show
+    pub fn controller_mode(&self) -> Option<ControllerMode> {
+        match &self.backend {
+            BackendConfig::AbEip(_) => {self.controller.as_ref().map(|identity| identity.mode())}
+            BackendConfig::Modbus(_) => {
+                let answering = self.alarms_active.value().is_some() || matches!(self.alarms_active.reason(), Some(UnknownReason::TagError(_)));
+                answering.then_some(ControllerMode::Online)
+            }
+        }
+    }
+
+    pub fn controller_info(&self) -> String {
+        let route = match &self.backend {
+            BackendConfig::AbEip(path) => {format!("EtherNet/IP, path {path}")}
+            BackendConfig::Modbus(config) => {format!("Modbus TCP, port {}, unit {}", config.port, config.unit_id)}
+        };
+        let route = format!("{route}, {} reset", self.reset_mode);
+        match (&self.controller, &self.backend) {
+            (None, _) => {format!("Controller ({route}): unknown")}
+            (Some(identity), BackendConfig::Modbus(_)) => {
+                format!("Controller ({route}): {} rev {}", identity.product_name, identity.revision_string())
+            }
+            (Some(identity), BackendConfig::AbEip(_)) => {
+                format!("Controller ({route}): {}\nMode: {}, key switch: {}{}", identity, identity.mode(), identity.key_switch(),
+                        if identity.major_fault() {", MAJOR FAULT"} else {""})
+            }
+        }
+    }
+
+    // None while the system doesn't answer
+    pub fn local_address_info(&self) -> Option<String> {
+        let address = self.local_address?;
+        match &self.local_interface {
+            None => {Some(format!("Reached through: {address}"))}
+            Some(interface) => {Some(format!("Reached through: {interface} ({address})"))}
+        }
+    }
+
+    pub fn clock_info(&self) -> String {
+        match self.clock_drift {
+            None => {"Controller clock: unknown".to_string()}
+            Some(drift) => {
+                let seconds = drift.num_milliseconds() as f64 / 1000.0;
+                let local = (Local::now() + drift).format("%Y-%m-%d %H:%M:%S");
+                format!("Controller clock: {local}, {seconds:+.1} s from this computer")
+            }
+        }
+    }
+
     pub fn failed_hosts(&self) -> String {
         let mut failed_hosts = vec![];
         failed_hosts.extend(self.plc_eths.iter().filter(|host| !host.responding));
         failed_hosts.extend(self.plc_nodes.iter().filter(|host| !host.responding));
         failed_hosts.into_iter()
-            .map(|host| host.hostname.to_string())
+            .map(|host| match host.bootp_requesting {
+                true => {format!("{} (requesting BOOTP)", host.hostname)}
+                false => {host.hostname.to_string()}
+            })
             .collect::<Vec<String>>()
             .join("\n")
     }
     
+    pub fn links_ok(&self) -> bool {
+        self.plc_eths.iter().all(|host| host.link.as_ref().is_none_or(|link| link.ok()))
+    }
+
+    pub fn link_info(&self) -> String {
+        self.plc_eths.iter()
+            .filter_map(|host| Some(format!("{}: {}", host.hostname, host.link.as_ref()?)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn has_identity_checks(&self) -> bool {
+        self.hosts().any(|host| host.expected_identity.is_some())
+    }
+
+    fn identity_checks(&self) -> impl Iterator<Item = (&Host, &IdentityCheck)> {
+        self.hosts().filter(|host| host.expected_identity.is_some()).map(|host| (host, &host.identity_check))
+    }
+
+    pub fn identity_status(&self) -> String {
+        let checked = self.identity_checks().count();
+        let matching = self.identity_checks().filter(|(_, check)| **check == IdentityCheck::Matches).count();
+        match self.identity_checks().filter(|(_, check)| **check == IdentityCheck::Unknown).count() {
+            0 => {format!("{matching}/{checked}")}
+            unknown => {format!("{matching}/{checked}, {unknown} unknown")}
+        }
+    }
+
+    // None while a host that isn't responding can't be checked, a silent host is no proof of the right device
+    pub fn identities_ok(&self) -> Option<bool> {
+        if self.identity_checks().any(|(_, check)| matches!(check, IdentityCheck::Mismatch(_))) {
+            return Some(false);
+        }
+        match self.identity_checks().all(|(_, check)| *check == IdentityCheck::Matches) {
+            true => {Some(true)}
+            false => {None}
+        }
+    }
+
+    pub fn identity_mismatches(&self) -> String {
+        self.identity_checks()
+            .filter_map(|(host, check)| match check {
+                IdentityCheck::Matches => {None}
+                IdentityCheck::Unknown => {Some(format!("{}: unknown, not responding", host.hostname))}
+                IdentityCheck::Mismatch(reason) => {Some(format!("{}: {reason}", host.hostname))}
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
     pub fn eths_ok(&self) -> bool {
         self.plc_eths.iter().all(|host| host.responding)
     }
@@ -204,9 +1038,117 @@ pub struct Host {
     hostname: String,
     ip_address: String,
     responding: bool,
+    expected_identity: Option<ExpectedIdentity>,
+    identity_check: IdentityCheck,
+    identity: Option<DeviceIdentity>,  // last ListIdentity reply, None once the host stops answering
+    identity_read: Option<Instant>,
+    link: Option<LinkDiagnostics>,
+    mac: Option<Mac>,
+    bootp_requesting: bool,
 }
 impl Host {
     pub fn new(hostname: String, ip_address: String) -> Self {
-        Host {hostname, ip_address, responding: false}
+        Host {hostname, ip_address, responding: false, expected_identity: None, identity_check: IdentityCheck::Unknown, identity: None, identity_read: None, link: None, mac: None, bootp_requesting: false}
+    }
+
+    pub fn set_mac(&mut self, mac: Mac) -> Result<(), String> {
+        self.ip_address.parse::<Ipv4Addr>().map_err(|_| format!("{} has no valid address to reserve", self.hostname))?;
+        self.mac = Some(mac);
+        Ok(())
+    }
+
+    pub fn set_expected_identity(&mut self, expected_identity: ExpectedIdentity) {
+        self.expected_identity = Some(expected_identity);
+    }
+}
+
+// BOOTP reservations of every host with a mac address in the config
+fn refused(session: &Session, action: &str) -> String {
+    format!("{action} refused, {} is logged in as {}", session.username, session.role)
+}
+
+fn reservations(system_infos: &HashMap<String, SystemInfo>) -> HashMap<Mac, (String, Ipv4Addr)> {
+    system_infos.values()
+        .flat_map(|system_info| system_info.hosts())
+        .filter_map(|host| Some((host.mac?, (host.hostname.to_string(), host.ip_address.parse().ok()?))))
+        .collect()
+}
+
+// Counter or toggling bit the PLC logic changes every cycle. The controller keeps answering
+// reads when the logic is stopped or stuck, so only a value that stops changing shows it.
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    tag: String,
+    timeout: Duration,
+    last_value: Option<PlcValue>,
+    last_change: Option<Instant>,
+}
+impl Heartbeat {
+    pub fn new(tag: &str, timeout: Duration) -> Self {
+        Heartbeat {tag: tag.to_string(), timeout, last_value: None, last_change: None}
+    }
+
+    // start over when the value couldn't be read, the time without contact says nothing about the logic
+    fn update(&mut self, value: Option<&PlcValue>) {
+        match value {
+            None => {
+                self.last_value = None;
+                self.last_change = None;
+            }
+            Some(value) => {
+                if self.last_value.as_ref() != Some(value) {
+                    self.last_value = Some(*value);
+                    self.last_change = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    pub fn frozen(&self) -> Option<bool> {
+        Some(self.last_change?.elapsed() > self.timeout)
     }
-}
\ No newline at end of file
+}
+impl std::fmt::Display for Heartbeat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.last_value, self.last_change) {
+            (Some(value), Some(last_change)) => {
+                write!(f, "Heartbeat {}: {}, last change {} s ago (limit {} s)", self.tag, value,
+                       last_change.elapsed().as_secs(), self.timeout.as_secs())?;
+                if self.frozen() == Some(true) {
+                    write!(f, " - PLC LOGIC NOT RUNNING")?;
+                }
+                Ok(())
+            }
+            _ => {write!(f, "Heartbeat {}: unknown", self.tag)}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkDiagnostics {
+    counters: LinkCounters,
+    errors_rising: bool,
+}
+impl LinkDiagnostics {
+    pub fn ok(&self) -> bool {
+        self.counters.link_up && self.counters.full_duplex && !self.errors_rising
+    }
+}
+impl std::fmt::Display for LinkDiagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let counters = &self.counters;
+        write!(f, "{} Mbps {} duplex, in errors {}, out errors {}, CRC errors {}, alignment errors {}",
+               counters.speed, if counters.full_duplex {"full"} else {"half"},
+               counters.in_errors, counters.out_errors, counters.fcs_errors, counters.alignment_errors)?;
+        if !counters.link_up {
+            write!(f, " - LINK DOWN")?;
+        }
+        if !counters.full_duplex {
+            write!(f, " - HALF DUPLEX")?;
+        }
+        if self.errors_rising {
+            write!(f, " - ERRORS RISING")?;
+        }
+        Ok(())
+    }
+}
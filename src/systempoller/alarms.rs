@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use chrono::{DateTime, Local};
+use crate::systempoller::plc_comms::{BackendConfig, PlcValue, TagSpec, UnknownReason, ValueType};
+
+// One alarm of a system: a BOOL tag, or a bit of an alarm word ("B_Sys_Alarms[0].5", "40010.3")
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmDefinition {
+    pub tag: String,
+    pub bit: Option<u8>,
+    pub description: String,
+}
+impl AlarmDefinition {
+    pub fn parse(tag: &str, description: &str) -> Result<Self, String> {
+        let (tag, bit) = match tag.rsplit_once(".") {
+            Some((word, bit)) if !bit.is_empty() && bit.chars().all(|c| c.is_ascii_digit()) => {
+                let bit: u8 = bit.parse().ok().filter(|bit| *bit < 32).ok_or(format!("invalid bit number in {tag:?}, expected 0-31"))?;
+                (word, Some(bit))
+            }
+            _ => {(tag, None)}
+        };
+        if tag.is_empty() {
+            return Err("missing alarm tag".to_string());
+        }
+        Ok(AlarmDefinition {tag: tag.to_string(), bit, description: description.to_string()})
+    }
+
+    // alarm words are DINTs in Logix and holding/input registers over Modbus
+    pub fn tag_spec(&self, backend: &BackendConfig) -> TagSpec {
+        let value_type = match (self.bit, backend) {
+            (None, _) => {ValueType::Bool}
+            (Some(_), BackendConfig::AbEip(_)) => {ValueType::Dint}
+            (Some(_), BackendConfig::Modbus(_)) => {ValueType::Int}
+        };
+        TagSpec {name: self.tag.to_string(), value_type}
+    }
+
+    // a Modbus register only has 16 bits, higher ones would never read as active
+    pub fn check_bit(&self, backend: &BackendConfig) -> Result<(), String> {
+        match (self.bit, backend) {
+            (Some(bit), BackendConfig::Modbus(_)) if bit >= 16 => {Err(format!("bit {bit} of {} is beyond a 16-bit modbus register, expected 0-15", self.tag))}
+            _ => {Ok(())}
+        }
+    }
+
+    // None if the tag wasn't read
+    pub fn active(&self, tags: &BTreeMap<String, PlcValue>) -> Option<bool> {
+        let word = tags.get(&self.tag)?.as_i64()?;
+        Some((word >> self.bit.unwrap_or(0)) & 1 == 1)
+    }
+
+    pub fn address(&self) -> String {
+        match self.bit {
+            None => {self.tag.to_string()}
+            Some(bit) => {format!("{}.{}", self.tag, bit)}
+        }
+    }
+}
+
+// Tags the PLC logic latches on the first alarm of a trip: the alarm number
+// (1-based order of the @alarm lines, 0 when clear) and the time as LINT microseconds since 1970
+#[derive(Clone, Debug)]
+pub struct FirstOutTags {
+    pub cause: String,
+    pub time: Option<String>,
+}
+impl FirstOutTags {
+    pub fn tag_specs(&self, backend: &BackendConfig) -> Vec<TagSpec> {
+        let cause_type = match backend {
+            BackendConfig::AbEip(_) => {ValueType::Dint}
+            BackendConfig::Modbus(_) => {ValueType::Int}
+        };
+        let mut tag_specs = vec![TagSpec {name: self.cause.to_string(), value_type: cause_type}];
+        tag_specs.extend(self.time.iter().map(|time| TagSpec {name: time.to_string(), value_type: ValueType::Lint}));
+        tag_specs
+    }
+
+    // None while the controller reports no first out or it couldn't be read
+    pub fn read(&self, tags: &BTreeMap<String, PlcValue>, alarms: &[AlarmDefinition]) -> Option<FirstOut> {
+        let number = tags.get(&self.cause)?.as_i64().filter(|number| *number != 0)?;
+        let description = match usize::try_from(number - 1).ok().and_then(|index| alarms.get(index)) {
+            Some(alarm) => {alarm.description.to_string()}
+            None => {format!("alarm {number}")}
+        };
+        let time = self.time.as_ref()
+            .and_then(|time| tags.get(time)?.as_i64())
+            .and_then(DateTime::from_timestamp_micros)
+            .map(|time| time.with_timezone(&Local));
+        Some(FirstOut {number, description, time})
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FirstOut {
+    pub number: i64,
+    pub description: String,
+    pub time: Option<DateTime<Local>>,
+}
+impl fmt::Display for FirstOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time {
+            Some(time) => {write!(f, "{} at {}", self.description, time.format("%Y-%m-%d %H:%M:%S%.3f"))}
+            None => {write!(f, "{}, time unknown", self.description)}
+        }
+    }
+}
+
+// Sum alarm of a system, with the last value that was read so an unknown state still tells something
+#[derive(Clone, Debug)]
+pub struct AlarmState {
+    current: Result<bool, UnknownReason>,
+    last_known: Option<(bool, DateTime<Local>)>,
+}
+impl Default for AlarmState {
+    fn default() -> Self {
+        AlarmState {current: Err(UnknownReason::NotPolled), last_known: None}
+    }
+}
+impl AlarmState {
+    pub fn update(&mut self, current: Result<bool, UnknownReason>) {
+        if let Ok(value) = current {
+            self.last_known = Some((value, Local::now()));
+        }
+        self.current = current;
+    }
+
+    pub fn value(&self) -> Option<bool> {
+        self.current.as_ref().ok().copied()
+    }
+
+    pub fn reason(&self) -> Option<&UnknownReason> {
+        self.current.as_ref().err()
+    }
+}
+impl fmt::Display for AlarmState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.current {
+            Ok(value) => {write!(f, "Alarms active: {value}")}
+            Err(reason) => {
+                write!(f, "Alarms active: unknown, {reason} - {}", reason.advice())?;
+                match self.last_known {
+                    None => {write!(f, "\nNever read")}
+                    Some((value, time)) => {write!(f, "\nLast known: {value} at {}", time.format("%Y-%m-%d %H:%M:%S"))}
+                }
+            }
+        }
+    }
+}
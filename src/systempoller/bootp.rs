@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const MIN_PACKET_LENGTH: usize = 236;  // fixed BOOTP fields up to the options
+const REQUEST_MEMORY: Duration = Duration::from_secs(30);  // a request counts as current this long
+const LEASE_TIME: u32 = 86400;
+const RECEIVE_ERROR_BACKOFF: Duration = Duration::from_millis(500);  // e.g. ICMP port unreachable on Windows, or the adapter going away
+
+// DHCP options
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+
+pub type Mac = [u8; 6];
+
+#[derive(Clone, Debug)]
+pub struct BootpRequest {
+    pub mac: String,
+    pub hostname: Option<String>,
+    pub address: Option<Ipv4Addr>,
+    pub seconds_ago: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct BootpSettings {
+    pub netmask: Ipv4Addr,
+    pub gateway: Option<Ipv4Addr>,
+}
+impl Default for BootpSettings {
+    fn default() -> Self {
+        BootpSettings {netmask: Ipv4Addr::new(255, 255, 255, 0), gateway: None}
+    }
+}
+
+struct BootpState {
+    reservations: HashMap<Mac, (String, Ipv4Addr)>,  // mac -> hostname, address
+    requests: HashMap<Mac, Instant>,
+    settings: BootpSettings,
+    errors: Vec<String>,  // not yet shown to the operator
+}
+
+// Answers BOOTP and DHCP requests from MAC addresses in the reservation table,
+// and remembers every request so unknown modules can be assigned from the GUI.
+pub struct BootpServer {
+    state: Arc<Mutex<BootpState>>,
+    task: JoinHandle<()>,
+}
+impl BootpServer {
+    pub async fn start(reservations: HashMap<Mac, (String, Ipv4Addr)>, settings: BootpSettings) -> Result<Self, String> {
+        let socket = UdpSocket::bind(("0.0.0.0", SERVER_PORT)).await
+            .map_err(|e| format!("BOOTP server can't listen on port {SERVER_PORT}: {e}"))?;
+        socket.set_broadcast(true).map_err(|e| e.to_string())?;
+        let state = Arc::new(Mutex::new(BootpState {reservations, requests: HashMap::new(), settings, errors: vec![]}));
+        let task_state = state.clone();
+        let task = tokio::spawn(async move {serve(socket, task_state).await});
+        Ok(BootpServer {state, task})
+    }
+
+    pub fn set_reservations(&self, reservations: HashMap<Mac, (String, Ipv4Addr)>, settings: BootpSettings) {
+        let mut state = self.state.lock().unwrap();
+        state.reservations = reservations;
+        state.settings = settings;
+    }
+
+    pub fn assign(&self, mac: Mac, hostname: String, address: Ipv4Addr) {
+        let mut state = self.state.lock().unwrap();
+        state.reservations.retain(|_, (_, reserved)| *reserved != address);
+        state.reservations.insert(mac, (hostname, address));
+    }
+
+    // requests seen recently, newest first
+    pub fn requests(&self) -> Vec<BootpRequest> {
+        let mut state = self.state.lock().unwrap();
+        state.requests.retain(|_, seen| seen.elapsed() < REQUEST_MEMORY);
+        let mut requests = state.requests.iter()
+            .map(|(mac, seen)| {
+                let reservation = state.reservations.get(mac);
+                BootpRequest {
+                    mac: format_mac(mac),
+                    hostname: reservation.map(|(hostname, _)| hostname.to_string()),
+                    address: reservation.map(|(_, address)| *address),
+                    seconds_ago: seen.elapsed().as_secs(),
+                }
+            })
+            .collect::<Vec<BootpRequest>>();
+        requests.sort_by_key(|request| request.seconds_ago);
+        requests
+    }
+
+    pub fn take_errors(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.lock().unwrap().errors)
+    }
+
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(socket: UdpSocket, state: Arc<Mutex<BootpState>>) {
+    let mut buffer = [0u8; 1500];
+    loop {
+        let Ok((length, _)) = socket.recv_from(&mut buffer).await else {
+            sleep(RECEIVE_ERROR_BACKOFF).await;  // the error usually repeats at once
+            continue;
+        };
+        let request = &buffer[..length];
+        if length < MIN_PACKET_LENGTH || request[0] != 1 || request[1] != 1 || request[2] != 6 {
+            continue;  // not an ethernet BOOTREQUEST
+        }
+        let mac: Mac = request[28..34].try_into().unwrap();
+        let (reservation, settings) = {
+            let mut state = state.lock().unwrap();
+            state.requests.insert(mac, Instant::now());
+            (state.reservations.get(&mac).map(|(_, address)| *address), state.settings.clone())
+        };
+        let Some(address) = reservation else {
+            continue;
+        };
+        let server_address = local_address_towards(address).await.unwrap_or(Ipv4Addr::UNSPECIFIED);
+        let reply = match dhcp_option(request, OPTION_MESSAGE_TYPE).and_then(|value| value.first().copied()) {
+            None => {build_reply(request, address, server_address, None, &settings)}  // plain BOOTP
+            Some(1) => {build_reply(request, address, server_address, Some(2), &settings)}  // discover -> offer
+            Some(3) => {
+                match check_dhcp_request(request, address, server_address) {
+                    RequestCheck::OtherServer => {continue}
+                    RequestCheck::WrongAddress => {build_nak(request, server_address)}  // start over with a discover
+                    RequestCheck::Ok => {build_reply(request, address, server_address, Some(5), &settings)}  // request -> ack
+                }
+            }
+            Some(_) => {continue}
+        };
+        let destination = SocketAddr::from((Ipv4Addr::BROADCAST, CLIENT_PORT));
+        if let Err(e) = socket.send_to(&reply, destination).await {
+            state.lock().unwrap().errors.push(format!("BOOTP reply to {} failed: {e}", format_mac(&mac)));
+        }
+    }
+}
+
+// value of a DHCP option, None for plain BOOTP or if the option isn't there
+fn dhcp_option(request: &[u8], code: u8) -> Option<&[u8]> {
+    if request.get(MIN_PACKET_LENGTH..MIN_PACKET_LENGTH + 4)? != MAGIC_COOKIE {
+        return None;
+    }
+    let mut i = MIN_PACKET_LENGTH + 4;
+    while i < request.len() {
+        match request[i] {
+            0 => {i += 1}  // padding
+            255 => {return None}
+            option => {
+                let length = *request.get(i + 1)? as usize;
+                if option == code {
+                    return request.get(i + 2..i + 2 + length);
+                }
+                i += 2 + length;
+            }
+        }
+    }
+    None
+}
+
+fn option_address(request: &[u8], code: u8) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = dhcp_option(request, code)?.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+#[derive(Debug, PartialEq)]
+enum RequestCheck {
+    Ok,
+    OtherServer,  // the client took another server's offer
+    WrongAddress,  // the client asks for an address other than its reservation
+}
+
+// A DHCPREQUEST names the chosen server when answering an offer, and the address it wants
+// in option 50 or, when renewing, in ciaddr
+fn check_dhcp_request(request: &[u8], address: Ipv4Addr, server_address: Ipv4Addr) -> RequestCheck {
+    if option_address(request, OPTION_SERVER_ID).is_some_and(|server_id| server_id != server_address) {
+        return RequestCheck::OtherServer;
+    }
+    let client_address = Ipv4Addr::new(request[12], request[13], request[14], request[15]);
+    let requested = option_address(request, OPTION_REQUESTED_ADDRESS)
+        .or(Some(client_address).filter(|client_address| !client_address.is_unspecified()));
+    match requested {
+        Some(requested) if requested != address => {RequestCheck::WrongAddress}
+        _ => {RequestCheck::Ok}
+    }
+}
+
+fn build_reply(request: &[u8], address: Ipv4Addr, server_address: Ipv4Addr, dhcp_type: Option<u8>, settings: &BootpSettings) -> Vec<u8> {
+    let mut reply = vec![0u8; MIN_PACKET_LENGTH];
+    reply[0] = 2;  // BOOTREPLY
+    reply[1..3].copy_from_slice(&request[1..3]);  // hardware type and length
+    reply[4..8].copy_from_slice(&request[4..8]);  // transaction id
+    reply[10..12].copy_from_slice(&request[10..12]);  // flags
+    reply[16..20].copy_from_slice(&address.octets());  // yiaddr
+    reply[20..24].copy_from_slice(&server_address.octets());  // siaddr
+    reply[28..44].copy_from_slice(&request[28..44]);  // chaddr
+
+    reply.extend_from_slice(&MAGIC_COOKIE);
+    if let Some(dhcp_type) = dhcp_type {
+        reply.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, dhcp_type]);
+        reply.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+        reply.extend_from_slice(&server_address.octets());
+        reply.extend_from_slice(&[51, 4]);
+        reply.extend_from_slice(&LEASE_TIME.to_be_bytes());
+    }
+    reply.extend_from_slice(&[1, 4]);
+    reply.extend_from_slice(&settings.netmask.octets());
+    if let Some(gateway) = settings.gateway {
+        reply.extend_from_slice(&[3, 4]);
+        reply.extend_from_slice(&gateway.octets());
+    }
+    reply.push(255);
+    reply.resize(reply.len().max(300), 0);  // some clients drop replies shorter than a full BOOTP packet
+    reply
+}
+
+fn build_nak(request: &[u8], server_address: Ipv4Addr) -> Vec<u8> {
+    let mut reply = vec![0u8; MIN_PACKET_LENGTH];
+    reply[0] = 2;  // BOOTREPLY
+    reply[1..3].copy_from_slice(&request[1..3]);  // hardware type and length
+    reply[4..8].copy_from_slice(&request[4..8]);  // transaction id
+    reply[10..12].copy_from_slice(&request[10..12]);  // flags
+    reply[28..44].copy_from_slice(&request[28..44]);  // chaddr
+
+    reply.extend_from_slice(&MAGIC_COOKIE);
+    reply.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, 6]);  // DHCPNAK
+    reply.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+    reply.extend_from_slice(&server_address.octets());
+    reply.push(255);
+    reply.resize(reply.len().max(300), 0);
+    reply
+}
+
+// local address the OS would use to reach the target
+pub async fn local_address_towards(target: Ipv4Addr) -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+    socket.connect((target, CLIENT_PORT)).await.ok()?;
+    match socket.local_addr().ok()?.ip() {
+        std::net::IpAddr::V4(address) => {Some(address)}
+        std::net::IpAddr::V6(_) => {None}
+    }
+}
+
+// name of the network adapter holding a local address, e.g. "Ethernet 2"
+pub fn interface_name(address: Ipv4Addr) -> Option<String> {
+    if_addrs::get_if_addrs().ok()?.into_iter()
+        .find(|interface| interface.ip() == address)
+        .map(|interface| interface.name)
+}
+
+// first IPv4 address of a network adapter, by name
+pub fn interface_address(name: &str) -> Option<Ipv4Addr> {
+    if_addrs::get_if_addrs().ok()?.into_iter()
+        .filter(|interface| interface.name == name)
+        .find_map(|interface| match interface.ip() {
+            std::net::IpAddr::V4(address) => {Some(address)}
+            std::net::IpAddr::V6(_) => {None}
+        })
+}
+
+pub fn parse_mac(value: &str) -> Option<Mac> {
+    let bytes = value.split([':', '-']).map(|part| u8::from_str_radix(part, 16).ok()).collect::<Option<Vec<u8>>>()?;
+    bytes.try_into().ok()
+}
+
+pub fn format_mac(mac: &Mac) -> String {
+    mac.iter().map(|byte| format!("{byte:02X}")).collect::<Vec<String>>().join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: Mac = [0x00, 0x1D, 0x9C, 0x01, 0x02, 0x03];
+    const RESERVED: Ipv4Addr = Ipv4Addr::new(10, 1, 0, 20);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(10, 1, 0, 1);
+
+    // ethernet BOOTREQUEST with the given DHCP options, plain BOOTP without
+    fn request(options: Option<&[u8]>) -> Vec<u8> {
+        let mut request = vec![0u8; MIN_PACKET_LENGTH];
+        request[0..3].copy_from_slice(&[1, 1, 6]);
+        request[4..8].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        request[10] = 0x80;  // broadcast flag
+        request[28..34].copy_from_slice(&MAC);
+        if let Some(options) = options {
+            request.extend_from_slice(&MAGIC_COOKIE);
+            request.extend_from_slice(options);
+            request.push(255);
+        }
+        request
+    }
+
+    #[test]
+    fn options_are_found_past_padding() {
+        let padded = request(Some(&[0, 0, OPTION_MESSAGE_TYPE, 1, 3, OPTION_REQUESTED_ADDRESS, 4, 10, 1, 0, 20]));
+        assert_eq!(dhcp_option(&padded, OPTION_MESSAGE_TYPE), Some(&[3u8][..]));
+        assert_eq!(option_address(&padded, OPTION_REQUESTED_ADDRESS), Some(RESERVED));
+        assert_eq!(dhcp_option(&padded, OPTION_SERVER_ID), None);
+    }
+
+    #[test]
+    fn plain_bootp_and_truncated_options_have_no_options() {
+        assert_eq!(dhcp_option(&request(None), OPTION_MESSAGE_TYPE), None);
+        let mut truncated = request(Some(&[]));
+        truncated.pop();
+        truncated.extend_from_slice(&[OPTION_REQUESTED_ADDRESS, 4, 10, 1]);
+        assert_eq!(option_address(&truncated, OPTION_REQUESTED_ADDRESS), None);
+    }
+
+    #[test]
+    fn request_for_another_server_is_ignored() {
+        let selecting = request(Some(&[OPTION_MESSAGE_TYPE, 1, 3, OPTION_SERVER_ID, 4, 10, 1, 0, 2, OPTION_REQUESTED_ADDRESS, 4, 10, 1, 0, 20]));
+        assert_eq!(check_dhcp_request(&selecting, RESERVED, SERVER), RequestCheck::OtherServer);
+    }
+
+    #[test]
+    fn request_for_another_address_is_refused() {
+        let selecting = request(Some(&[OPTION_MESSAGE_TYPE, 1, 3, OPTION_SERVER_ID, 4, 10, 1, 0, 1, OPTION_REQUESTED_ADDRESS, 4, 10, 1, 0, 99]));
+        assert_eq!(check_dhcp_request(&selecting, RESERVED, SERVER), RequestCheck::WrongAddress);
+
+        let mut renewing = request(Some(&[OPTION_MESSAGE_TYPE, 1, 3]));
+        renewing[12..16].copy_from_slice(&[10, 1, 0, 99]);  // ciaddr
+        assert_eq!(check_dhcp_request(&renewing, RESERVED, SERVER), RequestCheck::WrongAddress);
+    }
+
+    #[test]
+    fn request_for_the_reservation_is_acknowledged() {
+        let selecting = request(Some(&[OPTION_MESSAGE_TYPE, 1, 3, OPTION_SERVER_ID, 4, 10, 1, 0, 1, OPTION_REQUESTED_ADDRESS, 4, 10, 1, 0, 20]));
+        assert_eq!(check_dhcp_request(&selecting, RESERVED, SERVER), RequestCheck::Ok);
+
+        let mut renewing = request(Some(&[OPTION_MESSAGE_TYPE, 1, 3]));
+        renewing[12..16].copy_from_slice(&RESERVED.octets());
+        assert_eq!(check_dhcp_request(&renewing, RESERVED, SERVER), RequestCheck::Ok);
+    }
+
+    #[test]
+    fn offer_carries_the_reservation_and_settings() {
+        let settings = BootpSettings {netmask: Ipv4Addr::new(255, 255, 0, 0), gateway: Some(Ipv4Addr::new(10, 1, 0, 254))};
+        let reply = build_reply(&request(Some(&[OPTION_MESSAGE_TYPE, 1, 1])), RESERVED, SERVER, Some(2), &settings);
+        assert_eq!(reply[0], 2);
+        assert_eq!(&reply[4..8], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(reply[10], 0x80);
+        assert_eq!(&reply[16..20], &RESERVED.octets());
+        assert_eq!(&reply[20..24], &SERVER.octets());
+        assert_eq!(&reply[28..34], &MAC);
+        assert!(reply.len() >= 300);
+        assert_eq!(dhcp_option(&reply, OPTION_MESSAGE_TYPE), Some(&[2u8][..]));
+        assert_eq!(option_address(&reply, OPTION_SERVER_ID), Some(SERVER));
+        assert_eq!(dhcp_option(&reply, 51), Some(&LEASE_TIME.to_be_bytes()[..]));
+        assert_eq!(option_address(&reply, 1), Some(settings.netmask));
+        assert_eq!(option_address(&reply, 3), settings.gateway);
+    }
+
+    #[test]
+    fn bootp_reply_has_no_dhcp_type() {
+        let reply = build_reply(&request(None), RESERVED, SERVER, None, &BootpSettings::default());
+        assert_eq!(dhcp_option(&reply, OPTION_MESSAGE_TYPE), None);
+        assert_eq!(option_address(&reply, 1), Some(Ipv4Addr::new(255, 255, 255, 0)));
+        assert_eq!(option_address(&reply, 3), None);
+    }
+
+    #[test]
+    fn nak_offers_no_address() {
+        let reply = build_nak(&request(Some(&[OPTION_MESSAGE_TYPE, 1, 3])), SERVER);
+        assert_eq!(&reply[16..20], &[0, 0, 0, 0]);
+        assert_eq!(&reply[28..34], &MAC);
+        assert_eq!(dhcp_option(&reply, OPTION_MESSAGE_TYPE), Some(&[6u8][..]));
+        assert_eq!(option_address(&reply, OPTION_SERVER_ID), Some(SERVER));
+    }
+
+    #[test]
+    fn mac_round_trip() {
+        assert_eq!(parse_mac("00:1d:9c:01:02:03"), Some(MAC));
+        assert_eq!(parse_mac("00-1D-9C-01-02-03"), Some(MAC));
+        assert_eq!(format_mac(&MAC), "00:1D:9C:01:02:03");
+        assert_eq!(parse_mac("00:1D:9C:01:02"), None);
+    }
+}
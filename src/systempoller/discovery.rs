@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Duration, Instant};
+use crate::systempoller::SystemInfo;
+use crate::systempoller::identity::{DeviceIdentity, list_identities, list_identity_request, parse_list_identity_reply};
+
+const BROADCAST_WAIT: Duration = Duration::from_millis(2000);
+const MAX_SWEEP_HOSTS: usize = 1024;
+
+// Devices answering ListIdentity, by broadcast and unicast to every address in the subnets
+pub async fn discover(subnets: &[String], source: Option<Ipv4Addr>) -> Result<BTreeMap<Ipv4Addr, DeviceIdentity>, String> {
+    let mut devices = broadcast(source).await.map_err(|e| format!("ListIdentity broadcast failed: {e}"))?;
+
+    let mut addresses = vec![];
+    for subnet in subnets {
+        addresses.extend(parse_cidr(subnet)?);
+    }
+    let sweep = list_identities(addresses.iter().map(|address| address.to_string()).collect(), source).await;
+    for (address, identity) in sweep {
+        if let (Ok(address), Some(identity)) = (address.parse(), identity) {
+            devices.insert(address, identity);
+        }
+    }
+    Ok(devices)
+}
+
+async fn broadcast(source: Option<Ipv4Addr>) -> std::io::Result<BTreeMap<Ipv4Addr, DeviceIdentity>> {
+    let socket = UdpSocket::bind((source.unwrap_or(Ipv4Addr::UNSPECIFIED), 0)).await?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&list_identity_request(), (Ipv4Addr::BROADCAST, 44818)).await?;
+
+    let mut devices = BTreeMap::new();
+    let mut buffer = [0u8; 1024];
+    let deadline = Instant::now() + BROADCAST_WAIT;
+    while let Ok(Ok((length, sender))) = timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+        if let (std::net::IpAddr::V4(address), Some(identity)) = (sender.ip(), parse_list_identity_reply(&buffer[..length])) {
+            devices.insert(address, identity);
+        }
+    }
+    Ok(devices)
+}
+
+// host addresses of a subnet like "10.1.2.0/24"
+pub fn parse_cidr(subnet: &str) -> Result<Vec<Ipv4Addr>, String> {
+    let invalid = || format!("invalid subnet {subnet:?}, expected address/prefix");
+    let (address, prefix) = subnet.trim().split_once("/").ok_or_else(invalid)?;
+    let address: Ipv4Addr = address.parse().map_err(|_| invalid())?;
+    let prefix: u32 = prefix.parse().ok().filter(|prefix| *prefix <= 32).ok_or_else(invalid)?;
+    let size = 1u64 << (32 - prefix);
+    if size as usize > MAX_SWEEP_HOSTS {
+        return Err(format!("subnet {subnet} is too large to sweep, use /22 or smaller"));
+    }
+    let network = u32::from(address) & !((size - 1) as u32);
+    let hosts = match size {
+        1 | 2 => {(0..size as u32).collect::<Vec<u32>>()}
+        _ => {(1..size as u32 - 1).collect()}  // skip network and broadcast address
+    };
+    Ok(hosts.into_iter().map(|host| Ipv4Addr::from(network + host)).collect())
+}
+
+pub fn vendor_name(vendor_id: u16) -> String {
+    match vendor_id {
+        1 => {"Rockwell Automation/Allen-Bradley".to_string()}
+        283 => {"Hilscher".to_string()}
+        674 => {"Siemens".to_string()}
+        _ => {format!("vendor {vendor_id}")}
+    }
+}
+
+// CIP device profiles seen on the network
+pub fn device_type_name(device_type: u16) -> String {
+    match device_type {
+        0x02 => {"AC drive".to_string()}
+        0x07 => {"discrete I/O".to_string()}
+        0x0C => {"communications adapter".to_string()}
+        0x0E => {"controller".to_string()}
+        0x18 => {"HMI".to_string()}
+        0x2B => {"generic device".to_string()}
+        _ => {format!("device type 0x{device_type:02X}")}
+    }
+}
+
+// compare what answered with the loaded config
+pub fn report(devices: &BTreeMap<Ipv4Addr, DeviceIdentity>, system_infos: &HashMap<String, SystemInfo>) -> String {
+    let configured = system_infos.values()
+        .flat_map(|system_info| system_info.hosts())
+        .map(|host| (host.ip_address.to_string(), host))
+        .collect::<HashMap<_, _>>();
+
+    let mut lines = vec![format!("{} devices answered:", devices.len())];
+    lines.extend(devices.iter().map(|(address, identity)| format!("{}  {}  {}  {}  serial {:08X}",
+        address, vendor_name(identity.vendor_id), device_type_name(identity.device_type), identity.product_name, identity.serial_number)));
+
+    lines.push("\nFound but not configured:".to_string());
+    lines.extend(devices.iter()
+        .filter(|(address, _)| !configured.contains_key(&address.to_string()))
+        .map(|(address, identity)| format!("{}  {}", address, identity.product_name)));
+
+    lines.push("\nConfigured but not found:".to_string());
+    let mut missing = configured.iter()
+        .filter(|(address, _)| address.parse().map_or(true, |address| !devices.contains_key(&address)))
+        .map(|(address, host)| format!("{}  {}", address, host.hostname))
+        .collect::<Vec<String>>();
+    missing.sort();
+    lines.extend(missing);
+
+    lines.push("\nUnexpected identities:".to_string());
+    for (address, identity) in devices {
+        let Some(host) = configured.get(&address.to_string()) else {
+            continue;
+        };
+        if let Some(expected) = &host.expected_identity {
+            let mismatches = expected.mismatches(identity);
+            if !mismatches.is_empty() {
+                lines.push(format!("{}  {}: {} ({})", address, host.hostname, identity.product_name, mismatches.join(", ")));
+            }
+        }
+    }
+    lines.join("\n")
+}
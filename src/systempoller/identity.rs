@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::fmt;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+const EIP_PORT: u16 = 44818;
+const LIST_IDENTITY: u16 = 0x0063;
+const ENCAPSULATION_HEADER_LENGTH: usize = 24;
+// item count, item type, item length, protocol version and socket address precede the identity
+const IDENTITY_OFFSET: usize = ENCAPSULATION_HEADER_LENGTH + 2 + 2 + 2 + 2 + 16;
+
+// Contents of the CIP Identity object (class 0x01), as returned by
+// Get Attributes All and in ListIdentity replies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceIdentity {
+    pub vendor_id: u16,
+    pub device_type: u16,
+    pub product_code: u16,
+    pub revision: (u8, u8),
+    pub status: u16,
+    pub serial_number: u32,
+    pub product_name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControllerMode {
+    Run,
+    Program,
+    Faulted,
+    Unknown,
+    Online,  // answers but has no mode to report, Modbus panels
+}
+impl fmt::Display for ControllerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerMode::Run => {write!(f, "Run")}
+            ControllerMode::Program => {write!(f, "Program")}
+            ControllerMode::Faulted => {write!(f, "Faulted")}
+            ControllerMode::Unknown => {write!(f, "Unknown")}
+            ControllerMode::Online => {write!(f, "Online")}
+        }
+    }
+}
+
+impl DeviceIdentity {
+    // parse identity attributes 1-7, starting at vendor id
+    pub fn parse(data: &[u8]) -> Option<DeviceIdentity> {
+        let name_length = *data.get(14)? as usize;
+        let name = data.get(15..15 + name_length)?;
+        Some(DeviceIdentity {
+            vendor_id: u16::from_le_bytes([data[0], data[1]]),
+            device_type: u16::from_le_bytes([data[2], data[3]]),
+            product_code: u16::from_le_bytes([data[4], data[5]]),
+            revision: (data[6], data[7]),
+            status: u16::from_le_bytes([data[8], data[9]]),
+            serial_number: u32::from_le_bytes([data[10], data[11], data[12], data[13]]),
+            product_name: String::from_utf8_lossy(name).trim().to_string(),
+        })
+    }
+
+    pub fn revision_string(&self) -> String {
+        format!("{}.{:03}", self.revision.0, self.revision.1)
+    }
+
+    pub fn major_fault(&self) -> bool {
+        self.status & 0x0C00 != 0  // major recoverable / unrecoverable fault bits
+    }
+
+    // Logix controllers report their mode in the extended device status bits 4-7
+    pub fn mode(&self) -> ControllerMode {
+        if self.major_fault() {
+            return ControllerMode::Faulted;
+        }
+        match (self.status >> 4) & 0x0F {
+            0b0101 => {ControllerMode::Faulted}
+            0b0110 => {ControllerMode::Run}
+            0b0111 => {ControllerMode::Program}
+            _ => {ControllerMode::Unknown}
+        }
+    }
+
+    // position of the key switch, vendor specific bits 12-13 on Logix controllers
+    pub fn key_switch(&self) -> &'static str {
+        match (self.status >> 12) & 0b11 {
+            0b01 => {"Run"}
+            0b10 => {"Program"}
+            0b11 => {"Remote"}
+            _ => {"Unknown"}
+        }
+    }
+}
+impl fmt::Display for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rev {}, serial {:08X}, status 0x{:04X}", self.product_name, self.revision_string(), self.serial_number, self.status)
+    }
+}
+
+// Last comparison of a host with its expected identity
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum IdentityCheck {
+    #[default]
+    Unknown,  // not checked, the host isn't responding
+    Matches,
+    Mismatch(String),
+}
+
+// Expected identity of a host from the config, unset fields are not compared
+#[derive(Clone, Debug, Default)]
+pub struct ExpectedIdentity {
+    pub vendor_id: Option<u16>,
+    pub product_code: Option<u16>,
+    pub serial_number: Option<u32>,
+    pub revision: Option<(u8, u8)>,
+}
+impl ExpectedIdentity {
+    // fields as written in the config: decimal vendor id and product code, hex serial, "major.minor" revision, "*" to skip
+    pub fn parse(vendor_id: &str, product_code: &str, serial_number: &str, revision: &str) -> Result<ExpectedIdentity, String> {
+        Ok(ExpectedIdentity {
+            vendor_id: parse_optional(vendor_id, |value| value.parse().ok(), "vendor id")?,
+            product_code: parse_optional(product_code, |value| value.parse().ok(), "product code")?,
+            serial_number: parse_optional(serial_number, |value| u32::from_str_radix(value.trim_start_matches("0x"), 16).ok(), "serial number")?,
+            revision: parse_optional(revision, parse_revision, "revision")?,
+        })
+    }
+
+    pub fn mismatches(&self, actual: &DeviceIdentity) -> Vec<String> {
+        let mut mismatches = vec![];
+        if let Some(vendor_id) = self.vendor_id.filter(|vendor_id| *vendor_id != actual.vendor_id) {
+            mismatches.push(format!("vendor {} expected {}", actual.vendor_id, vendor_id));
+        }
+        if let Some(product_code) = self.product_code.filter(|product_code| *product_code != actual.product_code) {
+            mismatches.push(format!("product code {} expected {}", actual.product_code, product_code));
+        }
+        if let Some(serial_number) = self.serial_number.filter(|serial_number| *serial_number != actual.serial_number) {
+            mismatches.push(format!("serial {:08X} expected {:08X}", actual.serial_number, serial_number));
+        }
+        if let Some(revision) = self.revision.filter(|revision| *revision != actual.revision) {
+            mismatches.push(format!("revision {} expected {}.{:03}", actual.revision_string(), revision.0, revision.1));
+        }
+        mismatches
+    }
+}
+
+// Firmware expected for a device type, "major.minor" or "major" to accept any minor revision
+#[derive(Clone, Debug)]
+pub struct ExpectedFirmware {
+    pub vendor_id: u16,
+    pub product_code: u16,
+    pub major: u8,
+    pub minor: Option<u8>,
+}
+impl ExpectedFirmware {
+    pub fn parse(vendor_id: &str, product_code: &str, revision: &str) -> Result<ExpectedFirmware, String> {
+        let (major, minor) = match parse_revision(revision) {
+            Some((major, minor)) => {(major, Some(minor))}
+            None => {(revision.parse().map_err(|_| format!("invalid revision {revision:?}, expected major.minor or major"))?, None)}
+        };
+        Ok(ExpectedFirmware {
+            vendor_id: vendor_id.parse().map_err(|_| format!("invalid vendor id {vendor_id:?}"))?,
+            product_code: product_code.parse().map_err(|_| format!("invalid product code {product_code:?}"))?,
+            major,
+            minor,
+        })
+    }
+
+    pub fn applies_to(&self, identity: &DeviceIdentity) -> bool {
+        self.vendor_id == identity.vendor_id && self.product_code == identity.product_code
+    }
+
+    pub fn matches(&self, identity: &DeviceIdentity) -> bool {
+        identity.revision.0 == self.major && self.minor.map_or(true, |minor| identity.revision.1 == minor)
+    }
+
+    pub fn revision_string(&self) -> String {
+        match self.minor {
+            None => {format!("{}.x", self.major)}
+            Some(minor) => {format!("{}.{:03}", self.major, minor)}
+        }
+    }
+}
+
+pub fn parse_revision(value: &str) -> Option<(u8, u8)> {
+    let (major, minor) = value.split_once(".")?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+fn parse_optional<T>(value: &str, parse: impl Fn(&str) -> Option<T>, name: &str) -> Result<Option<T>, String> {
+    match value {
+        "" | "*" => {Ok(None)}
+        _ => {parse(value).map(Some).ok_or(format!("invalid {name} {value:?}"))}
+    }
+}
+
+// unicast ListIdentity to every address, hosts that don't answer in time map to None
+pub async fn list_identities(addresses: Vec<String>, source: Option<Ipv4Addr>) -> HashMap<String, Option<DeviceIdentity>> {
+    let mut set = JoinSet::new();
+    for address in addresses {
+        set.spawn(async move {
+            let identity = timeout(Duration::from_millis(1000), list_identity(&address, source)).await.ok().flatten();
+            (address, identity)
+        });
+    }
+    let mut map = HashMap::new();
+    while let Some(res) = set.join_next().await {
+        if let Ok((address, identity)) = res {
+            map.insert(address, identity);
+        }
+    }
+    map
+}
+
+async fn list_identity(address: &str, source: Option<Ipv4Addr>) -> Option<DeviceIdentity> {
+    let socket = UdpSocket::bind((source.unwrap_or(Ipv4Addr::UNSPECIFIED), 0)).await.ok()?;
+    socket.connect((address, EIP_PORT)).await.ok()?;
+    socket.send(&list_identity_request()).await.ok()?;
+    let mut buffer = [0u8; 1024];
+    let length = socket.recv(&mut buffer).await.ok()?;
+    parse_list_identity_reply(&buffer[..length])
+}
+
+pub fn list_identity_request() -> [u8; ENCAPSULATION_HEADER_LENGTH] {
+    let mut request = [0u8; ENCAPSULATION_HEADER_LENGTH];  // no data, session, status, context or options
+    request[0..2].copy_from_slice(&LIST_IDENTITY.to_le_bytes());
+    request
+}
+
+pub fn parse_list_identity_reply(data: &[u8]) -> Option<DeviceIdentity> {
+    if data.len() < IDENTITY_OFFSET || u16::from_le_bytes([data[0], data[1]]) != LIST_IDENTITY {
+        return None;
+    }
+    DeviceIdentity::parse(&data[IDENTITY_OFFSET..])
+}
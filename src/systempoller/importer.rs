@@ -1,32 +1,278 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::Duration;
 use crate::systempoller::{Host, SystemInfo};
+use crate::systempoller::identity::{ExpectedFirmware, ExpectedIdentity};
+use crate::systempoller::discovery::parse_cidr;
+use crate::systempoller::bootp::{BootpSettings, interface_address, parse_mac};
+use crate::systempoller::alarms::{AlarmDefinition, FirstOutTags};
+use crate::systempoller::safety::ExpectedSignature;
+use crate::systempoller::plc_comms::{BackendConfig, ConnectionPath, ModbusAddress, ModbusConfig, ResetMode, TagSpec, ValueType};
 
 use tokio::fs;
 
-pub async fn import(filename: &str) -> Result<HashMap<String, SystemInfo>, String> {
-    let mut system_infos = HashMap::new();
+#[derive(Default)]
+pub struct Config {
+    pub system_infos: HashMap<String, SystemInfo>,
+    pub subnets: Vec<String>,  // swept when discovering devices
+    pub bootp: BootpSettings,
+    pub source: Option<Ipv4Addr>,  // local address pings and PLC connections are sent from
+    pub references: Vec<Ipv4Addr>,  // checked to tell a disconnected laptop from a plant outage
+    systems_with_path: HashSet<String>,  // @path lines read so far
+}
+
+// Config is a list of "hostname, ip address" lines. Lines starting with "@" are settings,
+// applied after all hosts are read so they can refer to hosts anywhere in the file.
+pub async fn import(filename: &str) -> Result<Config, String> {
+    let mut config = Config::default();
     match fs::read_to_string(filename).await {
         Err(e) => {
             Err(e.to_string())
         }
         Ok(contents) => {
-            for line in contents.lines() {
-                let parts = line.split(",").collect::<Vec<&str>>();
+            let mut settings = vec![];
+            for (i, line) in contents.lines().enumerate() {
+                let parts = line.split(",").map(str::trim).collect::<Vec<&str>>();
+                if parts[0].starts_with("@") {
+                    settings.push((i + 1, parts));
+                    continue;
+                }
                 if parts.len() != 2 {  // if invalid line, skip it.
                     continue;
                 }
-                let hostname = parts[0].trim().to_string();
-                let ip_address = parts[1].trim().to_string();
-                let system_name = hostname.split("_").collect::<Vec<&str>>()[0].to_string();
-                let system_info = system_infos.entry(system_name.clone()).or_insert(SystemInfo::new(system_name));
-                if hostname.to_lowercase().contains("eth") {
+                let hostname = parts[0].to_string();
+                let ip_address = parts[1].to_string();
+                let system_name = system_name(&hostname);
+                let system_info = config.system_infos.entry(system_name.clone()).or_insert(SystemInfo::new(system_name));
+                if is_eth(&hostname) {
                     system_info.add_eth(Host::new(hostname, ip_address));
                 }
                 else {
                     system_info.add_node(Host::new(hostname, ip_address));
                 }
             }
-            Ok(system_infos)
+            for (line_number, parts) in settings {
+                apply_setting(&mut config, &parts).map_err(|e| format!("Line {line_number}: {e}"))?;
+            }
+            Ok(config)
+        }
+    }
+}
+
+// naming rules: system name is the hostname up to the first "_", PLC ethernet modules contain "eth"
+pub fn system_name(hostname: &str) -> String {
+    hostname.split("_").collect::<Vec<&str>>()[0].to_string()
+}
+
+pub fn is_eth(hostname: &str) -> bool {
+    hostname.to_lowercase().contains("eth")
+}
+
+fn apply_setting(config: &mut Config, parts: &[&str]) -> Result<(), String> {
+    let system_infos = &mut config.system_infos;
+    match parts[0] {
+        "@identity" => {
+            let [_, hostname, vendor_id, product_code, serial_number, revision] = parts else {
+                return Err("expected @identity, <hostname>, <vendor id>, <product code>, <serial>, <revision>".to_string());
+            };
+            let expected_identity = ExpectedIdentity::parse(vendor_id, product_code, serial_number, revision)?;
+            find_host(system_infos, hostname)?.set_expected_identity(expected_identity);
+        }
+        "@ring_supervisor" => {
+            let [_, system_name, hostname] = parts else {
+                return Err("expected @ring_supervisor, <system>, <hostname>".to_string());
+            };
+            find_system(system_infos, system_name)?.set_ring_supervisor(hostname)?;
+        }
+        "@backend" => {
+            let backend = match parts {
+                [_, system_name, "ab_eip"] => {
+                    match find_system(system_infos, system_name)?.backend() {
+                        BackendConfig::AbEip(path) => {BackendConfig::AbEip(path.clone())}  // keep a @path given earlier
+                        BackendConfig::Modbus(_) => {BackendConfig::default()}
+                    }
+                }
+                [_, system_name, "modbus", ..] if config.systems_with_path.contains(*system_name) => {
+                    return Err(format!("system {system_name} has a @path, a connection path only applies to ab_eip"));
+                }
+                [_, system_name, "modbus", ..] if find_system(system_infos, system_name)?.is_guardlogix() => {
+                    return Err(format!("system {system_name} is a @guardlogix, GuardLogix only applies to ab_eip"));
+                }
+                [_, _, "modbus", port, unit_id, alarm, resets @ ..] => {
+                    BackendConfig::Modbus(ModbusConfig {
+                        port: port.parse().map_err(|_| format!("invalid port {port:?}"))?,
+                        unit_id: unit_id.parse().map_err(|_| format!("invalid unit id {unit_id:?}"))?,
+                        alarm: ModbusAddress::parse(alarm)?,
+                        resets: resets.iter().map(|reset| match ModbusAddress::parse(reset)? {
+                            ModbusAddress::Coil(offset) => {Ok(ModbusAddress::Coil(offset))}
+                            _ => {Err(format!("reset address {reset} is not a coil"))}
+                        }).collect::<Result<Vec<ModbusAddress>, String>>()?,
+                    })
+                }
+                _ => {return Err("expected @backend, <system>, ab_eip or @backend, <system>, modbus, <port>, <unit id>, <alarm address>, <reset coils>...".to_string())}
+            };
+            let system_info = find_system(system_infos, parts[1])?;
+            for tag_name in system_info.tag_names() {  // given earlier
+                backend.check_tag_name(tag_name)?;
+            }
+            for alarm in system_info.alarm_definitions() {
+                alarm.check_bit(&backend)?;
+            }
+            system_info.set_backend(backend);
+        }
+        "@path" => {
+            let [_, system_name, route @ ..] = parts else {
+                return Err("expected @path, <system>, <port>, <link>[, <port>, <link>]...".to_string());
+            };
+            let path = ConnectionPath::parse(route)?;
+            let system_info = find_system(system_infos, system_name)?;
+            match system_info.backend() {
+                BackendConfig::AbEip(_) => {
+                    system_info.set_backend(BackendConfig::AbEip(path));
+                    config.systems_with_path.insert(system_name.to_string());
+                }
+                BackendConfig::Modbus(_) => {return Err(format!("system {system_name} uses modbus, a connection path only applies to ab_eip"))}
+            }
+        }
+        "@tag" => {
+            let [_, system_name, tag_name, value_type] = parts else {
+                return Err("expected @tag, <system>, <tag name>, <type>".to_string());
+            };
+            let value_type = ValueType::parse(value_type)?;
+            let system_info = find_system(system_infos, system_name)?;
+            system_info.backend().check_tag_name(tag_name)?;
+            system_info.add_tag(TagSpec {name: tag_name.to_string(), value_type});
+        }
+        "@heartbeat" => {
+            let [_, system_name, tag_name, value_type, seconds] = parts else {
+                return Err("expected @heartbeat, <system>, <tag name>, <type>, <seconds>".to_string());
+            };
+            let value_type = match ValueType::parse(value_type)? {
+                ValueType::Real => {return Err("heartbeat must be a bool or integer tag".to_string())}
+                value_type => {value_type}
+            };
+            let seconds: u64 = seconds.parse().ok().filter(|seconds| *seconds > 0)
+                .ok_or(format!("invalid heartbeat timeout {seconds:?}, expected whole seconds"))?;
+            let system_info = find_system(system_infos, system_name)?;
+            system_info.backend().check_tag_name(tag_name)?;
+            system_info.set_heartbeat(TagSpec {name: tag_name.to_string(), value_type}, Duration::from_secs(seconds));
+        }
+        "@alarm" => {
+            let [_, system_name, tag, description @ ..] = parts else {
+                return Err("expected @alarm, <system>, <tag>[.<bit>], <description>".to_string());
+            };
+            let alarm = AlarmDefinition::parse(tag, &description.join(", "))?;
+            let system_info = find_system(system_infos, system_name)?;
+            system_info.backend().check_tag_name(&alarm.tag)?;
+            alarm.check_bit(system_info.backend())?;
+            system_info.add_alarm(alarm);
+        }
+        "@first_out" => {
+            let (system_name, cause, time) = match parts {
+                [_, system_name, cause] => {(system_name, cause, None)}
+                [_, system_name, cause, time] => {(system_name, cause, Some(time.to_string()))}
+                _ => {return Err("expected @first_out, <system>, <alarm number tag>[, <timestamp tag>]".to_string())}
+            };
+            let system_info = find_system(system_infos, system_name)?;
+            for tag_name in [Some(*cause), time.as_deref()].into_iter().flatten() {
+                system_info.backend().check_tag_name(tag_name)?;
+            }
+            system_info.set_first_out_tags(FirstOutTags {cause: cause.to_string(), time});
+        }
+        "@reset_mode" => {
+            let (system_name, reset_mode) = match parts {
+                [_, system_name, "latch"] => {(system_name, ResetMode::Latch)}
+                [_, system_name, "pulse", milliseconds] => {
+                    let milliseconds: u64 = milliseconds.parse().ok().filter(|milliseconds| (50..=5000).contains(milliseconds))
+                        .ok_or(format!("invalid pulse width {milliseconds:?}, expected 50-5000 ms"))?;
+                    (system_name, ResetMode::Pulse(Duration::from_millis(milliseconds)))
+                }
+                _ => {return Err("expected @reset_mode, <system>, latch or @reset_mode, <system>, pulse, <milliseconds>".to_string())}
+            };
+            find_system(system_infos, system_name)?.set_reset_mode(reset_mode);
+        }
+        "@guardlogix" => {
+            let (system_name, expected_signature) = match parts {
+                [_, system_name] => {(system_name, None)}
+                [_, system_name, id] => {(system_name, Some(ExpectedSignature::parse(id, None)?))}
+                [_, system_name, id, time] => {(system_name, Some(ExpectedSignature::parse(id, Some(time))?))}
+                _ => {return Err("expected @guardlogix, <system>[, <expected safety signature ID (hex)>[, <signature time>]]".to_string())}
+            };
+            let system_info = find_system(system_infos, system_name)?;
+            match system_info.backend() {
+                BackendConfig::AbEip(_) => {system_info.set_guardlogix(expected_signature)}
+                BackendConfig::Modbus(_) => {return Err(format!("system {system_name} uses modbus, GuardLogix only applies to ab_eip"))}
+            }
+        }
+        "@firmware" => {
+            let [_, vendor_id, product_code, revision] = parts else {
+                return Err("expected @firmware, <vendor id>, <product code>, <revision>".to_string());
+            };
+            let expected_firmware = ExpectedFirmware::parse(vendor_id, product_code, revision)?;
+            for system_info in system_infos.values_mut() {
+                system_info.add_expected_firmware(expected_firmware.clone());
+            }
+        }
+        "@subnet" => {
+            let [_, subnet] = parts else {
+                return Err("expected @subnet, <address/prefix>".to_string());
+            };
+            parse_cidr(subnet)?;
+            config.subnets.push(subnet.to_string());
+        }
+        "@reservation" => {
+            let [_, hostname, mac] = parts else {
+                return Err("expected @reservation, <hostname>, <mac address>".to_string());
+            };
+            let mac = parse_mac(mac).ok_or(format!("invalid mac address {mac:?}"))?;
+            find_host(system_infos, hostname)?.set_mac(mac)?;
+        }
+        "@bootp" => {
+            let (netmask, gateway) = match parts {
+                [_, netmask] => {(netmask, None)}
+                [_, netmask, gateway] => {(netmask, Some(gateway))}
+                _ => {return Err("expected @bootp, <netmask>[, <gateway>]".to_string())}
+            };
+            config.bootp.netmask = netmask.parse().map_err(|_| format!("invalid netmask {netmask:?}"))?;
+            config.bootp.gateway = match gateway {
+                None => {None}
+                Some(gateway) => {Some(gateway.parse().map_err(|_| format!("invalid gateway {gateway:?}"))?)}
+            };
+        }
+        "@source" => {
+            let [_, source] = parts else {
+                return Err("expected @source, <local address or adapter name>".to_string());
+            };
+            // an adapter name is resolved once, here
+            let address = match source.parse::<Ipv4Addr>() {
+                Ok(address) => {address}
+                Err(_) => {interface_address(source).ok_or(format!("{source:?} is neither an address nor an adapter with an IPv4 address on this computer"))?}
+            };
+            UdpSocket::bind((address, 0)).map_err(|_| format!("{address} is not an address of this computer"))?;
+            config.source = Some(address);
+        }
+        "@reference" => {
+            let addresses = match parts {
+                [_, addresses @ ..] if !addresses.is_empty() => {addresses}
+                _ => {return Err("expected @reference, <address>...".to_string())}
+            };
+            for address in addresses {
+                config.references.push(address.parse().map_err(|_| format!("invalid reference address {address:?}"))?);
+            }
+        }
+        other => {
+            return Err(format!("unknown setting {other}"));
         }
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+fn find_system<'a>(system_infos: &'a mut HashMap<String, SystemInfo>, system_name: &str) -> Result<&'a mut SystemInfo, String> {
+    system_infos.get_mut(system_name).ok_or(format!("unknown system {system_name}"))
+}
+
+fn find_host<'a>(system_infos: &'a mut HashMap<String, SystemInfo>, hostname: &str) -> Result<&'a mut Host, String> {
+    system_infos.values_mut()
+        .find_map(|system_info| system_info.host_mut(hostname))
+        .ok_or(format!("unknown host {hostname}"))
+}
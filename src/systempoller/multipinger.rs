@@ -1,29 +1,48 @@
 use std::collections::HashMap;
 use std::env;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
 
 use tokio::process::{Command};
+use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
 
+const MAX_CONCURRENT_PINGS: usize = 128;  // each ping is a process, a large sweep would run out of them
+
 pub struct Multipinger{
     pub addresses:Vec<String>,
     pub arguments: Vec<String>,
 }
 impl Multipinger {
-    pub fn new(addresses:Vec<String>) -> Multipinger {
-        match env::consts::OS {
+    pub fn new(addresses:Vec<String>, source: Option<Ipv4Addr>) -> Multipinger {
+        let mut pinger = match env::consts::OS {
             "windows" => {Multipinger {addresses, arguments: vec!["-n".to_string(), "2".to_string(), "-w".to_string(), "1000".to_string()]}},
             "linux" =>   {Multipinger {addresses, arguments: vec!["-c".to_string(), "2".to_string(), "-W".to_string(), "1".to_string()]}},
             "macos" =>   {Multipinger {addresses, arguments: vec!["-c".to_string(), "2".to_string(), "-t".to_string(), "1".to_string()]}},
             _ =>         {Multipinger {addresses, arguments: vec!["-c".to_string(), "2".to_string(), "-W".to_string(), "1".to_string()]}},
 
+        };
+        // send from the chosen local address, linux ping calls it the interface
+        if let Some(source) = source {
+            let flag = match env::consts::OS {
+                "windows" | "macos" => {"-S"}
+                _ => {"-I"}
+            };
+            pinger.arguments.extend([flag.to_string(), source.to_string()]);
         }
+        pinger
     }
 
     pub async fn ping_all(&self) -> HashMap<String, bool>{
         let mut set = JoinSet::new();
+        let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_PINGS));
         for address in self.addresses.clone() {
             let argument_clone = self.arguments.clone();
-            set.spawn(async move {execute_ping(address, argument_clone).await});
+            let permits = permits.clone();
+            set.spawn(async move {
+                let _permit = permits.acquire().await;
+                execute_ping(address, argument_clone).await
+            });
         }
         let mut map = HashMap::new();
         while let Some(res) = set.join_next().await{
@@ -41,6 +60,7 @@ async fn execute_ping(target: String, mut arguments: Vec<String>) -> (String, bo
     let mut cmd = Command::new("ping");
     arguments.push(target.clone());
     cmd.args(arguments);
-    let res =cmd.output().await.unwrap();
-    (target, res.status.success())
+    // a ping that can't be started counts as not responding
+    let responding = cmd.output().await.is_ok_and(|res| res.status.success());
+    (target, responding)
 }
@@ -1,61 +1,856 @@
-use std::collections::HashMap;
+mod ab_eip;
+mod modbus;
+mod bound;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::Ipv4Addr;
 use std::time::Duration;
-use rseip::client::ab_eip::*;
-use rseip::ClientError;
+use bytes::Bytes;
+use chrono::{DateTime, TimeDelta, Utc};
 use rseip::precludes::*;
 use tokio::task::JoinSet;
+use tokio::time::{timeout, Instant};
+use tokio::time::error::Elapsed;
+use crate::audit::SharedAuditLog;
+use crate::systempoller::identity::DeviceIdentity;
+#[cfg(not(feature = "read-only"))]
+use crate::audit::AuditEntry;
+#[cfg(not(feature = "read-only"))]
 use tokio::time::sleep;
+use ab_eip::AbEipBackend;
+use bound::eip_client;
+pub use ab_eip::ConnectionPath;
+use modbus::ModbusBackend;
+pub use modbus::{ModbusAddress, ModbusConfig};
 
-pub async fn read_and_reset(plc_infos: Vec<(String, String, bool)>) -> HashMap<String, Option<bool>> {
-    let mut map = HashMap::from_iter(plc_infos.iter().map(|(name,_,_)|(name.to_string(), None)));
-    let mut set = JoinSet::new();
-    for (system_name, ip_address, reset) in plc_infos {
-        set.spawn(async move {alarms_active(system_name, &ip_address, reset).await});
-    }
-    
-    // ugly, but timeout on rseip calls are ~20s, instead we break after a reasonable duration
-    sleep(Duration::from_millis(1000)).await;
-    set.abort_all();
-    while let Some(tokio_result) = set.join_next().await{
-        match tokio_result {
-            Ok(plc_result) => {
-                match plc_result {
-                    Ok((system_name, value)) => {
-                        map.insert(system_name, Some(value));        
-                    }
-                    Err(_) => {}
+// true when built with the "read-only" feature, write_tag is then never compiled in
+pub const READ_ONLY_BUILD: bool = cfg!(feature = "read-only");
+
+const PLC_TIMEOUT: Duration = Duration::from_millis(1000);
+const BROWSE_TIMEOUT: Duration = Duration::from_secs(10);  // large programs take many requests
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+#[cfg(not(feature = "read-only"))]
+const PULSE_RELEASE_ATTEMPTS: u32 = 5;  // a reset tag left true blocks the next reset
+
+// What the poller needs from a PLC, regardless of vendor and protocol
+pub trait PlcBackend {
+    fn alarm_summary_tag(&self) -> String;
+    fn connected(&self) -> bool;
+    // all tags in as few requests as the protocol allows, outer error if the PLC couldn't be reached
+    async fn read_tags(&mut self, tags: &[TagSpec]) -> Result<HashMap<String, Result<PlcValue, String>>, String>;
+    fn reset_tags(&self) -> Vec<String>;
+    #[cfg(not(feature = "read-only"))]
+    async fn write_bool(&mut self, tag: &str, value: bool) -> Result<(), String>;
+    #[cfg(not(feature = "read-only"))]
+    async fn write_value(&mut self, tag: &str, value: PlcValue) -> Result<(), String>;
+    async fn list_tags(&mut self) -> Result<Vec<TagInfo>, String>;
+    async fn identity(&mut self) -> Option<DeviceIdentity>;
+    async fn wall_clock(&mut self) -> Option<DateTime<Utc>>;
+    #[cfg(not(feature = "read-only"))]
+    async fn set_wall_clock(&mut self, time: DateTime<Utc>) -> Result<(), String>;
+    async fn close(&mut self);
+}
+
+// Backend selected per system in the config
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackendConfig {
+    AbEip(ConnectionPath),
+    Modbus(ModbusConfig),
+}
+impl Default for BackendConfig {
+    fn default() -> Self {
+        BackendConfig::AbEip(ConnectionPath::default())
+    }
+}
+impl BackendConfig {
+    // checked when the config is loaded, so a typo doesn't wait for the first scan
+    pub fn check_tag_name(&self, tag: &str) -> Result<(), String> {
+        match self {
+            BackendConfig::AbEip(_) => {ab_eip::encode_tag_path(tag).map(|_| ())}
+            BackendConfig::Modbus(_) => {ModbusAddress::parse(tag).map(|_| ())}
+        }
+    }
+}
+
+pub enum Backend {
+    AbEip(AbEipBackend),
+    Modbus(ModbusBackend),
+}
+impl Backend {
+    pub fn new(request: &PlcRequest) -> Self {
+        match &request.backend {
+            BackendConfig::AbEip(path) => {Backend::AbEip(AbEipBackend::new(&request.system_name, &request.ip_address, path, request.source))}
+            BackendConfig::Modbus(modbus_config) => {Backend::Modbus(ModbusBackend::new(&request.ip_address, modbus_config, request.source))}
+        }
+    }
+}
+impl PlcBackend for Backend {
+    fn alarm_summary_tag(&self) -> String {
+        match self {
+            Backend::AbEip(backend) => {backend.alarm_summary_tag()}
+            Backend::Modbus(backend) => {backend.alarm_summary_tag()}
+        }
+    }
+    fn connected(&self) -> bool {
+        match self {
+            Backend::AbEip(backend) => {backend.connected()}
+            Backend::Modbus(backend) => {backend.connected()}
+        }
+    }
+    async fn read_tags(&mut self, tags: &[TagSpec]) -> Result<HashMap<String, Result<PlcValue, String>>, String> {
+        match self {
+            Backend::AbEip(backend) => {backend.read_tags(tags).await}
+            Backend::Modbus(backend) => {backend.read_tags(tags).await}
+        }
+    }
+    fn reset_tags(&self) -> Vec<String> {
+        match self {
+            Backend::AbEip(backend) => {backend.reset_tags()}
+            Backend::Modbus(backend) => {backend.reset_tags()}
+        }
+    }
+    #[cfg(not(feature = "read-only"))]
+    async fn write_bool(&mut self, tag: &str, value: bool) -> Result<(), String> {
+        match self {
+            Backend::AbEip(backend) => {backend.write_bool(tag, value).await}
+            Backend::Modbus(backend) => {backend.write_bool(tag, value).await}
+        }
+    }
+    #[cfg(not(feature = "read-only"))]
+    async fn write_value(&mut self, tag: &str, value: PlcValue) -> Result<(), String> {
+        match self {
+            Backend::AbEip(backend) => {backend.write_value(tag, value).await}
+            Backend::Modbus(backend) => {backend.write_value(tag, value).await}
+        }
+    }
+    async fn list_tags(&mut self) -> Result<Vec<TagInfo>, String> {
+        match self {
+            Backend::AbEip(backend) => {backend.list_tags().await}
+            Backend::Modbus(backend) => {backend.list_tags().await}
+        }
+    }
+    async fn identity(&mut self) -> Option<DeviceIdentity> {
+        match self {
+            Backend::AbEip(backend) => {backend.identity().await}
+            Backend::Modbus(backend) => {backend.identity().await}
+        }
+    }
+    async fn wall_clock(&mut self) -> Option<DateTime<Utc>> {
+        match self {
+            Backend::AbEip(backend) => {backend.wall_clock().await}
+            Backend::Modbus(backend) => {backend.wall_clock().await}
+        }
+    }
+    #[cfg(not(feature = "read-only"))]
+    async fn set_wall_clock(&mut self, time: DateTime<Utc>) -> Result<(), String> {
+        match self {
+            Backend::AbEip(backend) => {backend.set_wall_clock(time).await}
+            Backend::Modbus(backend) => {backend.set_wall_clock(time).await}
+        }
+    }
+    async fn close(&mut self) {
+        match self {
+            Backend::AbEip(backend) => {backend.close().await}
+            Backend::Modbus(backend) => {backend.close().await}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlcRequest {
+    pub system_name: String,
+    pub ip_address: String,
+    pub backend: BackendConfig,
+    pub tags: Vec<TagSpec>,  // read together with the sum alarm
+    pub reset: Option<String>,  // operator requesting a reset
+    pub reset_mode: ResetMode,
+    pub sync_clock: Option<String>,  // operator requesting the controller clock to be set
+    pub source: Option<Ipv4Addr>,  // local address to connect from
+}
+// Latch writes true and leaves clearing the reset tags to the PLC logic,
+// pulse writes true, then false after the pulse width for edge-triggered logic
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ResetMode {
+    #[default]
+    Latch,
+    Pulse(Duration),
+}
+impl fmt::Display for ResetMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResetMode::Latch => {write!(f, "latch")}
+            ResetMode::Pulse(width) => {write!(f, "pulse {} ms", width.as_millis())}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlcStatus {
+    pub alarms_active: Result<bool, UnknownReason>,
+    pub controller: Option<DeviceIdentity>,
+    pub tags: BTreeMap<String, PlcValue>,
+    pub clock_drift: Option<TimeDelta>,  // controller clock minus laptop clock
+}
+impl PlcStatus {
+    pub fn unknown(reason: UnknownReason) -> Self {
+        PlcStatus {alarms_active: Err(reason), controller: None, tags: BTreeMap::new(), clock_drift: None}
+    }
+}
+
+// Why the sum alarm couldn't be read, network problems and PLC program problems need different fixes
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum UnknownReason {
+    #[default]
+    NotPolled,
+    EthDown,
+    WaitingToReconnect,
+    TimedOut,
+    ConnectionFailed(String),
+    TagError(String),
+}
+impl UnknownReason {
+    pub fn short(&self) -> &'static str {
+        match self {
+            UnknownReason::NotPolled => {"not read"}
+            UnknownReason::EthDown => {"ETH down"}
+            UnknownReason::WaitingToReconnect => {"reconnecting"}
+            UnknownReason::TimedOut => {"timeout"}
+            UnknownReason::ConnectionFailed(_) => {"no connection"}
+            UnknownReason::TagError(_) => {"tag error"}
+        }
+    }
+
+    pub fn advice(&self) -> &'static str {
+        match self {
+            UnknownReason::NotPolled => {"wait for the next scan"}
+            UnknownReason::EthDown | UnknownReason::WaitingToReconnect | UnknownReason::ConnectionFailed(_) => {"check the network and the ETH module"}
+            UnknownReason::TimedOut => {"check the network load and the connection path"}
+            UnknownReason::TagError(_) => {"check the PLC program"}
+        }
+    }
+}
+impl fmt::Display for UnknownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownReason::NotPolled => {write!(f, "not read yet")}
+            UnknownReason::EthDown => {write!(f, "ETH module not responding, PLC not contacted")}
+            UnknownReason::WaitingToReconnect => {write!(f, "waiting to reconnect after failed attempts")}
+            UnknownReason::TimedOut => {write!(f, "read timed out")}
+            UnknownReason::ConnectionFailed(error_message) => {write!(f, "connection failed: {error_message}")}
+            UnknownReason::TagError(error_message) => {write!(f, "sum alarm tag: {error_message}")}
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueType {
+    Bool,
+    Sint,
+    Int,
+    Dint,
+    Lint,
+    Real,
+}
+impl ValueType {
+    pub const ALL: [ValueType; 6] = [ValueType::Bool, ValueType::Sint, ValueType::Int, ValueType::Dint, ValueType::Lint, ValueType::Real];
+
+    pub fn parse(value: &str) -> Result<ValueType, String> {
+        match value.to_lowercase().as_str() {
+            "bool" => {Ok(ValueType::Bool)}
+            "sint" => {Ok(ValueType::Sint)}
+            "int" => {Ok(ValueType::Int)}
+            "dint" => {Ok(ValueType::Dint)}
+            "lint" => {Ok(ValueType::Lint)}
+            "real" => {Ok(ValueType::Real)}
+            _ => {Err(format!("unknown tag type {value:?}, expected bool, sint, int, dint, lint or real"))}
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Bool => {write!(f, "BOOL")}
+            ValueType::Sint => {write!(f, "SINT")}
+            ValueType::Int => {write!(f, "INT")}
+            ValueType::Dint => {write!(f, "DINT")}
+            ValueType::Lint => {write!(f, "LINT")}
+            ValueType::Real => {write!(f, "REAL")}
+        }
+    }
+}
+
+// tag found by browsing the controller, value_type is None for structures and arrays
+#[derive(Clone, Debug)]
+pub struct TagInfo {
+    pub name: String,
+    pub value_type: Option<ValueType>,
+    pub type_text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagSpec {
+    pub name: String,
+    pub value_type: ValueType,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlcValue {
+    Bool(bool),
+    Sint(i8),
+    Int(i16),
+    Dint(i32),
+    Lint(i64),
+    Real(f32),
+}
+impl PlcValue {
+    pub fn parse(value_type: ValueType, value: &str) -> Result<PlcValue, String> {
+        let value = value.trim();
+        let invalid = || format!("{value:?} is not a valid {value_type}");
+        match value_type {
+            ValueType::Bool => {
+                match value.to_lowercase().as_str() {
+                    "true" | "1" => {Ok(PlcValue::Bool(true))}
+                    "false" | "0" => {Ok(PlcValue::Bool(false))}
+                    _ => {Err(invalid())}
                 }
-            },
-            Err(_) => {}
+            }
+            ValueType::Sint => {value.parse().map(PlcValue::Sint).map_err(|_| invalid())}
+            ValueType::Int => {value.parse().map(PlcValue::Int).map_err(|_| invalid())}
+            ValueType::Dint => {value.parse().map(PlcValue::Dint).map_err(|_| invalid())}
+            ValueType::Lint => {value.parse().map(PlcValue::Lint).map_err(|_| invalid())}
+            ValueType::Real => {value.parse().map(PlcValue::Real).map_err(|_| invalid())}
+        }
+    }
+
+    // integers and bools as a number, for alarm words and counters
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            PlcValue::Bool(value) => {Some(*value as i64)}
+            PlcValue::Sint(value) => {Some(*value as i64)}
+            PlcValue::Int(value) => {Some(*value as i64)}
+            PlcValue::Dint(value) => {Some(*value as i64)}
+            PlcValue::Lint(value) => {Some(*value)}
+            PlcValue::Real(_) => {None}
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            PlcValue::Bool(_) => {ValueType::Bool}
+            PlcValue::Sint(_) => {ValueType::Sint}
+            PlcValue::Int(_) => {ValueType::Int}
+            PlcValue::Dint(_) => {ValueType::Dint}
+            PlcValue::Lint(_) => {ValueType::Lint}
+            PlcValue::Real(_) => {ValueType::Real}
+        }
+    }
+}
+impl fmt::Display for PlcValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlcValue::Bool(value) => {write!(f, "{value}")}
+            PlcValue::Sint(value) => {write!(f, "{value}")}
+            PlcValue::Int(value) => {write!(f, "{value}")}
+            PlcValue::Dint(value) => {write!(f, "{value}")}
+            PlcValue::Lint(value) => {write!(f, "{value}")}
+            PlcValue::Real(value) => {write!(f, "{value}")}
         }
     }
-    map
 }
 
+// Long-lived session per PLC. A failed session is dropped and reconnected with increasing backoff,
+// so a dead controller doesn't get a new connection attempt every scan.
+struct Session {
+    ip_address: String,
+    config: BackendConfig,
+    source: Option<Ipv4Addr>,
+    backend: Option<Backend>,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+impl Session {
+    fn new(request: &PlcRequest) -> Self {
+        Session {ip_address: request.ip_address.to_string(), config: request.backend.clone(), source: request.source,
+                 backend: None, failures: 0, retry_at: None}
+    }
 
-async fn alarms_active(system_name: String, ip_address: &str, reset: bool) -> Result<(String, bool),ClientError> {
-    match AbEipClient::new_host_lookup(ip_address).await {
-        Ok(client) => {
-            let mut client = client.with_connection_path(PortSegment::default());
+    // a changed address, backend or source interface needs a new connection
+    fn serves(&self, request: &PlcRequest) -> bool {
+        self.ip_address == request.ip_address && self.config == request.backend && self.source == request.source
+    }
+
+    fn failed(&mut self) {
+        self.failures += 1;
+        let backoff = (MIN_RECONNECT_BACKOFF * 2u32.saturating_pow(self.failures - 1)).min(MAX_RECONNECT_BACKOFF);
+        self.retry_at = Some(Instant::now() + backoff);
+    }
+
+    fn succeeded(&mut self, backend: Backend) {
+        self.backend = Some(backend);
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
 
-            let tag = EPath::parse_tag(format!("B_{}_SumAlarm_hb", system_name)).unwrap();
-            
-            let res: TagValue<bool> = client.read_tag(tag.clone()).await?;  // exit early if error
+#[derive(Default)]
+pub struct SessionPool {
+    sessions: HashMap<String, Session>,  // system name -> session
+}
+impl SessionPool {
+    pub fn new() -> Self {
+        SessionPool::default()
+    }
 
-            if reset {  // if read failed don't try to reset
-                let auto_reset = EPath::parse_tag(format!("B_{}_Alarm_Reset_Auto_C", system_name)).unwrap();
-                let man_reset = EPath::parse_tag(format!("B_{}_Alarm_Reset_Man_C", system_name)).unwrap();
+    // backend of an existing session, so ad-hoc requests share the connection the poller uses
+    fn backend(&mut self, request: &PlcRequest) -> Result<&mut Backend, String> {
+        let session = self.sessions.entry(request.system_name.to_string()).or_insert(Session::new(request));
+        if !session.serves(request) {
+            *session = Session::new(request);
+        }
+        if session.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+            return Err("PLC not reachable, waiting to reconnect".to_string());
+        }
+        Ok(session.backend.get_or_insert_with(|| Backend::new(request)))
+    }
 
-                let value = TagValue {
-                    tag_type: TagType::Bool,
-                    value: true,
+    // a failed request leaves the connection in an unknown state
+    fn close_on_error<T>(&mut self, request: &PlcRequest, result: Result<Result<T, String>, Elapsed>) -> Result<T, String> {
+        let result = result.unwrap_or(Err("timed out".to_string()));
+        if result.is_err() {
+            if let Some(backend) = self.sessions.get_mut(&request.system_name).and_then(|session| session.backend.take()) {
+                tokio::spawn(close_backend(backend));
+            }
+        }
+        result
+    }
+
+    pub async fn read_tag(&mut self, request: &PlcRequest, tag_spec: TagSpec) -> Result<PlcValue, String> {
+        let backend = self.backend(request)?;
+        let result = timeout(PLC_TIMEOUT, backend.read_tags(std::slice::from_ref(&tag_spec))).await;
+        let mut values = self.close_on_error(request, result)?;
+        values.remove(&tag_spec.name).unwrap_or(Err(format!("{} was not read", tag_spec.name)))
+    }
+
+    #[cfg(not(feature = "read-only"))]
+    pub async fn write_tag(&mut self, request: &PlcRequest, tag: &str, value: PlcValue, audit: &SharedAuditLog, operator: &str) -> Result<(), String> {
+        let backend = self.backend(request)?;
+        let result = timeout(PLC_TIMEOUT, backend.write_value(tag, value)).await;
+        let result = self.close_on_error(request, result);
+        record_write(audit, request, operator, tag, &value.to_string(), result.clone().map(|_| "OK".to_string())).await;
+        result
+    }
+
+    #[cfg(feature = "read-only")]
+    pub async fn write_tag(&mut self, _request: &PlcRequest, _tag: &str, _value: PlcValue, _audit: &SharedAuditLog, _operator: &str) -> Result<(), String> {
+        Err("read-only build, nothing was written".to_string())
+    }
+
+    pub async fn read_and_reset(&mut self, plc_infos: Vec<PlcRequest>, audit: Option<SharedAuditLog>, read_only: bool) -> ScanResult {
+        let mut map = HashMap::from_iter(plc_infos.iter().map(|request|(request.system_name.to_string(), PlcStatus::unknown(UnknownReason::NotPolled))));
+        let mut dry_run_writes = vec![];
+        let mut not_written = vec![];
+
+        // sessions of systems not polled this scan (removed or eth down) are dead anyway
+        let mut stale = vec![];
+        self.sessions.retain(|system_name, session| {
+            let keep = plc_infos.iter().any(|request| &request.system_name == system_name && session.serves(request));
+            if !keep {
+                stale.extend(session.backend.take());
+            }
+            keep
+        });
+        for backend in stale {
+            tokio::spawn(close_backend(backend));
+        }
+
+        let mut set = JoinSet::new();
+        for request in plc_infos {
+            let session = self.sessions.entry(request.system_name.to_string()).or_insert(Session::new(&request));
+            if session.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+                not_written.extend(unwritten_requests(&request.system_name, request.reset.as_ref(), request.sync_clock.as_ref(), "PLC not reachable, waiting to reconnect"));
+                map.insert(request.system_name, PlcStatus::unknown(UnknownReason::WaitingToReconnect));
+                continue;
+            }
+            let mut backend = session.backend.take()
+                .unwrap_or(Backend::new(&request));
+            let audit = audit.clone();
+            set.spawn(async move {
+                // timeout on rseip calls are ~20s, instead we give up after a reasonable duration
+                let result = timeout(PLC_TIMEOUT, alarms_active(&mut backend, &request)).await
+                    .unwrap_or(Err((UnknownReason::TimedOut, None)));
+                // writes have their own timeouts, a cancelled write could reach the PLC without its audit entry
+                let (result, unwritten) = match result {
+                    Ok(status) => {
+                        let (skipped_writes, unwritten) = write_requested(&mut backend, &request, &status, audit, read_only).await;
+                        (Ok((status, skipped_writes)), unwritten)
+                    }
+                    Err((reason, controller)) => {
+                        let unwritten = unwritten_requests(&request.system_name, request.reset.as_ref(), request.sync_clock.as_ref(), &reason.to_string());
+                        (Err((reason, controller)), unwritten)
+                    }
                 };
-                let _ = client.write_tag(man_reset, value.clone()).await;
-                let _ = client.write_tag(auto_reset, value).await;
+                (request.system_name, backend, result, unwritten)
+            });
+        }
+
+        while let Some(tokio_result) = set.join_next().await {
+            let Ok((system_name, backend, result, unwritten)) = tokio_result else {
+                continue;
+            };
+            not_written.extend(unwritten);
+            let Some(session) = self.sessions.get_mut(&system_name) else {
+                continue;
+            };
+            match result {
+                Ok((status, skipped_writes)) => {
+                    session.succeeded(backend);
+                    map.insert(system_name, status);
+                    dry_run_writes.extend(skipped_writes);
+                }
+                Err((reason, controller)) => {
+                    tokio::spawn(close_backend(backend));  // state of the connection is unknown, start over
+                    session.failed();
+                    map.insert(system_name, PlcStatus {controller, ..PlcStatus::unknown(reason)});
+                }
+            }
+        }
+        ScanResult {statuses: map, dry_run_writes, not_written}
+    }
+}
+
+pub struct ScanResult {
+    pub statuses: HashMap<String, PlcStatus>,
+    pub dry_run_writes: Vec<String>,  // skipped in read-only mode
+    pub not_written: Vec<String>,  // requested resets and clock syncs that couldn't be attempted
+}
+
+// so the operator isn't left waiting for a reset that was dropped
+pub fn unwritten_requests(system_name: &str, reset: Option<&String>, sync_clock: Option<&String>, reason: &str) -> Vec<String> {
+    let mut messages = vec![];
+    if let Some(operator) = reset {
+        messages.push(format!("{system_name}: reset for {operator} not written, {reason}"));
+    }
+    if let Some(operator) = sync_clock {
+        messages.push(format!("{system_name}: clock sync for {operator} not written, {reason}"));
+    }
+    messages
+}
+
+// On its own connection, so a long browse or a backend that can't browse
+// doesn't hold up the scan or tear down the polling session
+pub async fn list_tags(request: PlcRequest) -> Result<Vec<TagInfo>, String> {
+    let mut backend = Backend::new(&request);
+    let result = timeout(BROWSE_TIMEOUT, backend.list_tags()).await.unwrap_or(Err("timed out".to_string()));
+    close_backend(backend).await;
+    result
+}
+
+// rseip close waits ~20s on a dead PLC, run detached or with a timeout
+async fn close_backend(mut backend: Backend) {
+    let _ = timeout(PLC_TIMEOUT, backend.close()).await;
+}
+
+// A missing sum alarm tag still returns the rest, the controller is reachable.
+// The identity is read even when the tags weren't, as long as the controller answered,
+// so a faulted controller or one in program mode isn't hidden behind the failed read
+async fn alarms_active(backend: &mut Backend, request: &PlcRequest) -> Result<PlcStatus, (UnknownReason, Option<DeviceIdentity>)> {
+    let summary_tag = backend.alarm_summary_tag();
+    let mut tag_specs = vec![TagSpec {name: summary_tag.clone(), value_type: ValueType::Bool}];
+    tag_specs.extend(request.tags.iter().cloned());
+    let read = backend.read_tags(&tag_specs).await;
+    let controller = match backend.connected() {
+        true => {backend.identity().await}
+        false => {None}
+    };
+    let mut values = match read {
+        Ok(values) => {values}
+        Err(error_message) => {return Err((UnknownReason::ConnectionFailed(error_message), controller))}
+    };
+    let alarms_active = match values.remove(&summary_tag) {
+        Some(Ok(PlcValue::Bool(value))) => {Ok(value)}
+        Some(Err(error_message)) => {Err(UnknownReason::TagError(error_message))}
+        _ => {Err(UnknownReason::TagError(format!("{summary_tag} is not a BOOL")))}
+    };
+    let tags = values.into_iter()
+        .filter_map(|(name, value)| Some((name, value.ok()?)))
+        .collect();
+    let clock_drift = backend.wall_clock().await.map(|clock| clock - Utc::now());
+    Ok(PlcStatus {alarms_active, controller, tags, clock_drift})
+}
+
+// resets and clock sync requested for this scan,
+// returns the writes skipped in read-only mode and the ones that couldn't be attempted
+async fn write_requested(backend: &mut Backend, request: &PlcRequest, status: &PlcStatus, audit: Option<SharedAuditLog>, read_only: bool) -> (Vec<String>, Vec<String>) {
+    let mut skipped_writes = vec![];
+    let mut not_written = vec![];
+    if let (Some(operator), Err(reason)) = (&request.reset, &status.alarms_active) {  // if read failed don't try to reset
+        not_written.push(format!("{}: reset for {operator} not written, {reason}", request.system_name));
+    }
+    if let (Some(operator), Ok(_)) = (&request.reset, &status.alarms_active) {
+        if read_only || READ_ONLY_BUILD {
+            let action = match request.reset_mode {
+                ResetMode::Latch => {"write true to".to_string()}
+                ResetMode::Pulse(width) => {format!("pulse true for {} ms to", width.as_millis())}
+            };
+            for reset_tag in backend.reset_tags() {
+                skipped_writes.push(format!("{}: would {} {} at {} for {}", request.system_name, action, reset_tag, request.ip_address, operator));
+            }
+        }
+        else if let Some(audit) = &audit {  // never write without an audit trail
+            match request.reset_mode {
+                ResetMode::Latch => {write_reset_tags(backend, request, audit, operator).await}
+                ResetMode::Pulse(width) => {
+                    // detached on its own connection, so the pulse width doesn't hold up the scan
+                    tokio::spawn(pulse_reset_tags(request.clone(), width, audit.clone(), operator.to_string()));
+                }
+            }
+        }
+    }
+    if let Some(operator) = &request.sync_clock {
+        if read_only || READ_ONLY_BUILD {
+            skipped_writes.push(format!("{}: would set the controller clock at {} for {}", request.system_name, request.ip_address, operator));
+        }
+        else if let Some(audit) = audit {
+            write_wall_clock(backend, request, &audit, operator).await;
+        }
+    }
+    (skipped_writes, not_written)
+}
+
+// latch: true is written and the PLC logic clears the tags
+#[cfg(not(feature = "read-only"))]
+async fn write_reset_tags(backend: &mut Backend, request: &PlcRequest, audit: &SharedAuditLog, operator: &str) {
+    for reset_tag in backend.reset_tags() {
+        let result = timeout(PLC_TIMEOUT, backend.write_bool(&reset_tag, true)).await
+            .unwrap_or(Err("timed out, state unknown".to_string()))
+            .map(|_| "OK".to_string());
+        record_write(audit, request, operator, &reset_tag, &true.to_string(), result).await;
+    }
+}
+
+// Pulse on its own connection with a timeout per step. Every tag gets the false write,
+// even if its true write failed or timed out, since the PLC may have received it anyway.
+#[cfg(not(feature = "read-only"))]
+async fn pulse_reset_tags(request: PlcRequest, width: Duration, audit: SharedAuditLog, operator: String) {
+    let mut backend = Backend::new(&request);
+    let reset_tags = backend.reset_tags();
+    for reset_tag in reset_tags.iter() {
+        let result = write_and_read_back(&mut backend, reset_tag, true).await;
+        if result.is_err() {
+            let _ = timeout(PLC_TIMEOUT, backend.close()).await;  // connection state unknown, the next write reconnects
+            backend = Backend::new(&request);
+        }
+        record_write(&audit, &request, &operator, reset_tag, &true.to_string(), result.map(|read_back| read_back_text(true, read_back))).await;
+    }
+    sleep(width).await;
+    for reset_tag in reset_tags.iter() {
+        let mut attempt = 1;
+        loop {
+            let result = write_and_read_back(&mut backend, reset_tag, false).await;
+            let confirmed = result == Ok(Some(false));
+            let result = result
+                .map(|read_back| read_back_text(false, read_back))
+                .map_err(|error_message| format!("attempt {attempt}/{PULSE_RELEASE_ATTEMPTS}: {error_message}"));
+            record_write(&audit, &request, &operator, reset_tag, &false.to_string(), result).await;
+            if confirmed {
+                break;
+            }
+            if attempt == PULSE_RELEASE_ATTEMPTS {
+                let aborted = Err(format!("ABORTED after {PULSE_RELEASE_ATTEMPTS} attempts, tag may still be true"));
+                record_write(&audit, &request, &operator, reset_tag, &false.to_string(), aborted).await;
+                break;
             }
-            let _ = client.close().await;
-            Ok((system_name, res.value))
+            let _ = timeout(PLC_TIMEOUT, backend.close()).await;
+            backend = Backend::new(&request);
+            sleep(PLC_TIMEOUT).await;
+            attempt += 1;
+        }
+    }
+    close_backend(backend).await;
+}
+
+// value read back after the write, None if the read failed.
+// A write is only confirmed by reading back, the PLC logic or another client may overwrite the tag.
+#[cfg(not(feature = "read-only"))]
+async fn write_and_read_back(backend: &mut Backend, tag: &str, value: bool) -> Result<Option<bool>, String> {
+    timeout(PLC_TIMEOUT, backend.write_bool(tag, value)).await
+        .unwrap_or(Err("timed out, state unknown".to_string()))?;
+    let tag_spec = TagSpec {name: tag.to_string(), value_type: ValueType::Bool};
+    let read = timeout(PLC_TIMEOUT, backend.read_tags(&[tag_spec])).await
+        .ok()
+        .and_then(|values| values.ok()?.remove(tag)?.ok());
+    match read {
+        Some(PlcValue::Bool(read_value)) => {Ok(Some(read_value))}
+        _ => {Ok(None)}
+    }
+}
+
+#[cfg(not(feature = "read-only"))]
+fn read_back_text(value: bool, read_back: Option<bool>) -> String {
+    match read_back {
+        Some(read_value) if read_value == value => {format!("OK, read back {value}")}
+        Some(read_value) => {format!("written, but read back {read_value}")}
+        None => {"written, read back failed".to_string()}
+    }
+}
+
+#[cfg(not(feature = "read-only"))]
+async fn record_write(audit: &SharedAuditLog, request: &PlcRequest, operator: &str, tag: &str, value: &str, result: Result<String, String>) {
+    let entry = AuditEntry {
+        operator: operator.to_string(),
+        system: request.system_name.to_string(),
+        plc_address: request.ip_address.to_string(),
+        tag: tag.to_string(),
+        value: value.to_string(),
+        result: match result {
+            Ok(result) => {result}
+            Err(error_message) => {error_message}
+        },
+    };
+    // a failure is kept in the log and shown to the operator after the scan
+    let _ = audit.lock().await.record(entry);
+}
+
+#[cfg(not(feature = "read-only"))]
+async fn write_wall_clock(backend: &mut Backend, request: &PlcRequest, audit: &SharedAuditLog, operator: &str) {
+    let time = Utc::now();
+    let result = timeout(PLC_TIMEOUT, backend.set_wall_clock(time)).await
+        .unwrap_or(Err("timed out, state unknown".to_string()))
+        .map(|_| "OK".to_string());
+    record_write(audit, request, operator, "WallClockTime", &time.to_rfc3339(), result).await;
+}
+
+// read-only builds never get here, but keep the signature so callers compile unchanged
+#[cfg(feature = "read-only")]
+async fn write_reset_tags(_backend: &mut Backend, _request: &PlcRequest, _audit: &SharedAuditLog, _operator: &str) {}
+
+#[cfg(feature = "read-only")]
+async fn pulse_reset_tags(_request: PlcRequest, _width: Duration, _audit: SharedAuditLog, _operator: String) {}
+
+#[cfg(feature = "read-only")]
+async fn write_wall_clock(_backend: &mut Backend, _request: &PlcRequest, _audit: &SharedAuditLog, _operator: &str) {}
+
+#[derive(Clone, Debug)]
+pub struct RingStatus {
+    pub ring_topology: bool,
+    pub network_status: u8,
+    pub fault_count: u16,
+    pub last_active_nodes: [Option<Ipv4Addr>; 2],  // neighbours of the break, seen from port 1 and 2
+}
+impl RingStatus {
+    pub fn ok(&self) -> bool {
+        self.ring_topology && self.network_status == 0
+    }
+
+    pub fn network_status_text(&self) -> &'static str {
+        match self.network_status {
+            0 => {"normal"}
+            1 => {"ring fault"}
+            2 => {"unexpected loop detected"}
+            3 => {"partial network fault"}
+            4 => {"rapid fault/restore cycle"}
+            _ => {"unknown status"}
         }
-        Err(err) => {Err(err.into()) },
     }
-}
\ No newline at end of file
+}
+
+// system name and address of its ring supervisor
+pub async fn read_ring_status(supervisors: Vec<(String, String)>, source: Option<Ipv4Addr>) -> HashMap<String, Option<RingStatus>> {
+    let mut map = HashMap::from_iter(supervisors.iter().map(|(name, _)|(name.to_string(), None)));
+    let mut set = JoinSet::new();
+    for (system_name, ip_address) in supervisors {
+        set.spawn(async move {
+            let status = timeout(Duration::from_millis(1000), ring_status(&ip_address, source)).await.ok().flatten();
+            (system_name, status)
+        });
+    }
+    while let Some(tokio_result) = set.join_next().await {
+        if let Ok((system_name, status)) = tokio_result {
+            map.insert(system_name, status);
+        }
+    }
+    map
+}
+
+// DLR object (class 0x47) of the supervisor module itself, so no routing to the backplane
+async fn ring_status(ip_address: &str, source: Option<Ipv4Addr>) -> Option<RingStatus> {
+    let mut client = eip_client(ip_address, source).await.ok()?.with_connection_path(EPath::default());
+    let attribute = |id: u16| EPath::default().with_class(0x47).with_instance(1).with_attribute(id);
+
+    let topology: u8 = client.get_attribute_single(attribute(1)).await.ok()?;
+    let network_status: u8 = client.get_attribute_single(attribute(2)).await.ok()?;
+    let fault_count: u16 = client.get_attribute_single(attribute(5)).await.unwrap_or(0);
+    let mut last_active_nodes = [None, None];
+    for (i, id) in [6, 7].into_iter().enumerate() {  // last active node on port 1 and 2
+        // UDINT ip address followed by the MAC address, zero when no break has been seen
+        let node: Option<Bytes> = client.get_attribute_single(attribute(id)).await.ok();
+        last_active_nodes[i] = node.filter(|data| data.len() >= 4)
+            .map(|data| u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+            .filter(|address| *address != 0)
+            .map(Ipv4Addr::from);
+    }
+    let _ = client.close().await;
+    Some(RingStatus {ring_topology: topology == 1, network_status, fault_count, last_active_nodes})
+}
+
+#[derive(Clone, Debug)]
+pub struct LinkCounters {
+    pub speed: u32,  // Mbps
+    pub link_up: bool,
+    pub full_duplex: bool,
+    pub in_errors: u32,
+    pub out_errors: u32,
+    pub alignment_errors: u32,
+    pub fcs_errors: u32,
+}
+impl LinkCounters {
+    pub fn total_errors(&self) -> u64 {
+        [self.in_errors, self.out_errors, self.alignment_errors, self.fcs_errors].iter().map(|count| *count as u64).sum()
+    }
+}
+
+pub async fn read_link_diagnostics(addresses: Vec<String>, source: Option<Ipv4Addr>) -> HashMap<String, Option<LinkCounters>> {
+    let mut set = JoinSet::new();
+    for ip_address in addresses {
+        set.spawn(async move {
+            let counters = timeout(Duration::from_millis(1000), link_counters(&ip_address, source)).await.ok().flatten();
+            (ip_address, counters)
+        });
+    }
+    let mut map = HashMap::new();
+    while let Some(tokio_result) = set.join_next().await {
+        if let Ok((ip_address, counters)) = tokio_result {
+            map.insert(ip_address, counters);
+        }
+    }
+    map
+}
+
+// Ethernet Link object (class 0xF6) of the module's first port
+async fn link_counters(ip_address: &str, source: Option<Ipv4Addr>) -> Option<LinkCounters> {
+    let mut client = eip_client(ip_address, source).await.ok()?.with_connection_path(EPath::default());
+    let attribute = |id: u16| EPath::default().with_class(0xF6).with_instance(1).with_attribute(id);
+
+    let speed: u32 = client.get_attribute_single(attribute(1)).await.ok()?;
+    let flags: u32 = client.get_attribute_single(attribute(2)).await.ok()?;
+    let interface_counters: Bytes = client.get_attribute_single(attribute(4)).await.ok()?;
+    let media_counters: Bytes = client.get_attribute_single(attribute(5)).await.ok()?;
+    let _ = client.close().await;
+
+    let counter = |data: &Bytes, index: usize| -> Option<u32> {
+        let bytes = data.get(index * 4..index * 4 + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    Some(LinkCounters {
+        speed,
+        link_up: flags & 0x01 != 0,
+        full_duplex: flags & 0x02 != 0,
+        in_errors: counter(&interface_counters, 4)?,
+        out_errors: counter(&interface_counters, 10)?,
+        alignment_errors: counter(&media_counters, 0)?,
+        fcs_errors: counter(&media_counters, 1)?,
+    })
+}
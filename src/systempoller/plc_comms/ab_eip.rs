@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use rseip::cip::epath::Segment;
+use rseip::cip::{MessageReply, MessageRequest};
+use rseip::precludes::*;
+use crate::systempoller::identity::DeviceIdentity;
+use crate::systempoller::plc_comms::bound::{eip_client, BoundClient};
+use crate::systempoller::plc_comms::{PlcBackend, PlcValue, TagInfo, TagSpec, ValueType};
+
+const READ_TAG: u8 = 0x4C;
+#[cfg(not(feature = "read-only"))]
+const WRITE_TAG: u8 = 0x4D;
+const GET_INSTANCE_ATTRIBUTE_LIST: u8 = 0x55;
+const SYMBOL_CLASS: u8 = 0x6B;
+const PARTIAL_TRANSFER: u8 = 0x06;
+const MULTIPLE_SERVICE_PACKET: u8 = 0x0A;
+const EMBEDDED_SERVICE_ERROR: u8 = 0x1E;
+const MAX_MESSAGE_SIZE: usize = 504;  // unconnected Logix request or reply, the controller rejects larger ones whole
+const PACKET_REQUEST_OVERHEAD: usize = 8;  // service, path size, message router path and service count
+const PACKET_REPLY_OVERHEAD: usize = 6;  // reply service, reserved, status, extended status size and service count
+const MAX_SERVICES_PER_PACKET: usize = 20;  // secondary cap, the size limit usually splits first
+const WALL_CLOCK_CLASS: u16 = 0x8B;
+#[cfg(not(feature = "read-only"))]
+const WALL_CLOCK_CURRENT_VALUE: u16 = 0x06;  // LINT microseconds since 1970, settable
+const WALL_CLOCK_CURRENT_UTC: u16 = 0x0B;  // LINT microseconds since 1970, UTC
+
+// Route from the ETH module to the controller, (port, link) per hop.
+// Link is a slot number on backplanes or the next module's address on networks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionPath {
+    hops: Vec<(u16, String)>,
+}
+impl Default for ConnectionPath {
+    fn default() -> Self {
+        ConnectionPath {hops: vec![(1, "0".to_string())]}  // backplane slot 0
+    }
+}
+impl ConnectionPath {
+    // alternating port and link values, e.g. ["1", "2"] or ["1", "2", "2", "10.0.0.5", "1", "0"]
+    pub fn parse(values: &[&str]) -> Result<ConnectionPath, String> {
+        if values.is_empty() || values.len() % 2 != 0 {
+            return Err("connection path needs port and link pairs".to_string());
+        }
+        let mut hops = vec![];
+        for pair in values.chunks(2) {
+            let port = pair[0].parse::<u16>().ok().filter(|port| *port != 0)
+                .ok_or(format!("invalid port {:?} in connection path", pair[0]))?;
+            let link = pair[1];
+            if link.parse::<u8>().is_err() && link.parse::<Ipv4Addr>().is_err() {
+                return Err(format!("invalid link {link:?} in connection path, expected a slot number or an IP address"));
+            }
+            hops.push((port, link.to_string()));
+        }
+        Ok(ConnectionPath {hops})
+    }
+
+    fn to_epath(&self) -> EPath {
+        let segments = self.hops.iter().map(|(port, link)| {
+            let link = match link.parse::<u8>() {
+                Ok(slot) => {Bytes::copy_from_slice(&[slot])}
+                Err(_) => {Bytes::copy_from_slice(link.as_bytes())}  // IP addresses are sent as text
+            };
+            Segment::Port(PortSegment {port: *port, link})
+        }).collect::<Vec<Segment>>();
+        EPath::from(segments)
+    }
+}
+impl fmt::Display for ConnectionPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hops = self.hops.iter().map(|(port, link)| format!("{port},{link}")).collect::<Vec<String>>();
+        write!(f, "{}", hops.join(" -> "))
+    }
+}
+
+// Allen-Bradley Logix controller over EtherNet/IP, tags named after the system
+pub struct AbEipBackend {
+    system_name: String,
+    ip_address: String,
+    path: ConnectionPath,
+    source: Option<Ipv4Addr>,
+    client: Option<BoundClient>,
+}
+impl AbEipBackend {
+    pub fn new(system_name: &str, ip_address: &str, path: &ConnectionPath, source: Option<Ipv4Addr>) -> Self {
+        AbEipBackend {system_name: system_name.to_string(), ip_address: ip_address.to_string(), path: path.clone(), source, client: None}
+    }
+
+    async fn client(&mut self) -> Result<&mut BoundClient, String> {
+        if self.client.is_none() {
+            let client = eip_client(&self.ip_address, self.source).await?;
+            self.client = Some(client.with_connection_path(self.path.to_epath()));
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+}
+
+impl AbEipBackend {
+    // embedded requests in one Multiple Service Packet to the message router, general status and data per reply
+    async fn send_services(&mut self, requests: Vec<&[u8]>) -> Result<Vec<(u8, Vec<u8>)>, String> {
+        let count = requests.len();
+        let data = multiple_service_packet(requests);
+        let path = EPath::default().with_class(0x02).with_instance(1);
+        let reply: MessageReply<Bytes> = self.client().await?
+            .send(MessageRequest::new(MULTIPLE_SERVICE_PACKET, path, Bytes::from(data))).await
+            .map_err(|e| e.to_string())?;
+        check_packet_status(reply.status.general)?;
+        let replies = parse_multiple_service_reply(&reply.data)?;
+        if replies.len() != count {
+            return Err(format!("expected {count} replies, got {}", replies.len()));
+        }
+        Ok(replies)
+    }
+
+    // Get Instance Attribute List on the symbol object, name and type of each symbol.
+    // Controllers answer with partial transfers, continued from the instance after the last one.
+    async fn browse_symbols(&mut self, program: Option<&str>) -> Result<Vec<(String, u16)>, String> {
+        let mut symbols = vec![];
+        let mut instance = 0u32;
+        loop {
+            let mut path = match program {
+                None => {vec![]}
+                Some(program) => {encode_tag_path(program)?}
+            };
+            path.extend_from_slice(&[0x20, SYMBOL_CLASS, 0x26, 0x00]);
+            path.extend_from_slice(&instance.to_le_bytes());
+            let mut request = vec![GET_INSTANCE_ATTRIBUTE_LIST, (path.len() / 2) as u8];
+            request.extend_from_slice(&path);
+            request.extend_from_slice(&[2, 0, 1, 0, 2, 0]);  // attributes 1 (name) and 2 (type)
+            let (status, data) = self.send_services(vec![&request]).await?.remove(0);
+            if status != 0 && status != PARTIAL_TRANSFER {
+                return Err(cip_status_text(status));
+            }
+            let mut i = 0;
+            while i + 8 <= data.len() {
+                instance = u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+                let name_length = u16::from_le_bytes([data[i + 4], data[i + 5]]) as usize;
+                let name = data.get(i + 6..i + 6 + name_length).ok_or("short symbol list")?;
+                let symbol_type = data.get(i + 6 + name_length..i + 8 + name_length).ok_or("short symbol list")?;
+                symbols.push((String::from_utf8_lossy(name).to_string(), u16::from_le_bytes([symbol_type[0], symbol_type[1]])));
+                i += 8 + name_length;
+            }
+            if status != PARTIAL_TRANSFER {
+                return Ok(symbols);
+            }
+            instance += 1;
+        }
+    }
+}
+
+impl PlcBackend for AbEipBackend {
+    fn alarm_summary_tag(&self) -> String {
+        format!("B_{}_SumAlarm_hb", self.system_name)
+    }
+
+    fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Read Tag services bundled into Multiple Service Packets sent to the message router
+    async fn read_tags(&mut self, tags: &[TagSpec]) -> Result<HashMap<String, Result<PlcValue, String>>, String> {
+        let mut values = HashMap::new();
+        let mut requests = vec![];
+        for tag in tags {
+            match read_tag_request(&tag.name) {
+                Ok(request) if PACKET_REQUEST_OVERHEAD + 2 + request.len() > MAX_MESSAGE_SIZE => {
+                    values.insert(tag.name.to_string(), Err(format!("tag name too long for one request ({} bytes)", request.len())));
+                }
+                Ok(request) => {requests.push((tag, request))}
+                Err(error_message) => {values.insert(tag.name.to_string(), Err(error_message));}
+            }
+        }
+        let sizes = requests.iter().map(|(tag, request)| (request.len(), read_reply_size(tag.value_type))).collect::<Vec<(usize, usize)>>();
+        let mut remaining = requests.as_slice();
+        for count in packet_counts(&sizes) {
+            let (chunk, rest) = remaining.split_at(count);
+            remaining = rest;
+            let replies = self.send_services(chunk.iter().map(|(_, request)| request.as_slice()).collect()).await?;
+            for ((tag, _), (status, data)) in chunk.iter().zip(replies) {
+                let value = check_status(status, data).and_then(|data| decode_value(&data))
+                    .and_then(|value| match value.value_type() == tag.value_type {
+                        true => {Ok(value)}
+                        false => {Err(format!("{} is {:?}, expected {:?}", tag.name, value.value_type(), tag.value_type))}
+                    });
+                values.insert(tag.name.to_string(), value);
+            }
+        }
+        Ok(values)
+    }
+
+    fn reset_tags(&self) -> Vec<String> {
+        vec![format!("B_{}_Alarm_Reset_Man_C", self.system_name), format!("B_{}_Alarm_Reset_Auto_C", self.system_name)]
+    }
+
+    #[cfg(not(feature = "read-only"))]
+    async fn write_bool(&mut self, tag: &str, value: bool) -> Result<(), String> {
+        self.write_value(tag, PlcValue::Bool(value)).await
+    }
+
+    #[cfg(not(feature = "read-only"))]
+    async fn write_value(&mut self, tag: &str, value: PlcValue) -> Result<(), String> {
+        let (type_code, data) = match value {
+            PlcValue::Bool(value) => {(0xC1u16, vec![if value {0xFF} else {0x00}])}
+            PlcValue::Sint(value) => {(0xC2, value.to_le_bytes().to_vec())}
+            PlcValue::Int(value) => {(0xC3, value.to_le_bytes().to_vec())}
+            PlcValue::Dint(value) => {(0xC4, value.to_le_bytes().to_vec())}
+            PlcValue::Lint(value) => {(0xC5, value.to_le_bytes().to_vec())}
+            PlcValue::Real(value) => {(0xCA, value.to_le_bytes().to_vec())}
+        };
+        let path = encode_tag_path(tag)?;
+        let mut request = vec![WRITE_TAG, (path.len() / 2) as u8];
+        request.extend_from_slice(&path);
+        request.extend_from_slice(&type_code.to_le_bytes());
+        request.extend_from_slice(&1u16.to_le_bytes());  // element count
+        request.extend_from_slice(&data);
+        let (status, data) = self.send_services(vec![&request]).await?.remove(0);
+        check_status(status, data).map(|_| ())
+    }
+
+    // controller scope symbols, then the symbols of every program
+    async fn list_tags(&mut self) -> Result<Vec<TagInfo>, String> {
+        let mut tags = vec![];
+        let mut programs = vec![];
+        for (name, symbol_type) in self.browse_symbols(None).await? {
+            if name.starts_with("Program:") {
+                programs.push(name);
+            }
+            else if let Some(tag) = tag_info(name, symbol_type) {
+                tags.push(tag);
+            }
+        }
+        for program in programs {
+            for (name, symbol_type) in self.browse_symbols(Some(&program)).await? {
+                tags.extend(tag_info(format!("{program}.{name}"), symbol_type));
+            }
+        }
+        Ok(tags)
+    }
+
+    // Identity object of the controller the connection path leads to
+    async fn identity(&mut self) -> Option<DeviceIdentity> {
+        let path = EPath::default().with_class(0x01).with_instance(1);
+        let data: Bytes = self.client().await.ok()?.get_attribute_all(path).await.ok()?;
+        DeviceIdentity::parse(&data)
+    }
+
+    async fn wall_clock(&mut self) -> Option<DateTime<Utc>> {
+        let path = EPath::default().with_class(WALL_CLOCK_CLASS).with_instance(1).with_attribute(WALL_CLOCK_CURRENT_UTC);
+        let data: Bytes = self.client().await.ok()?.get_attribute_single(path).await.ok()?;
+        DateTime::from_timestamp_micros(i64::from_le_bytes(data.get(..8)?.try_into().ok()?))
+    }
+
+    #[cfg(not(feature = "read-only"))]
+    async fn set_wall_clock(&mut self, time: DateTime<Utc>) -> Result<(), String> {
+        let path = EPath::default().with_class(WALL_CLOCK_CLASS).with_instance(1).with_attribute(WALL_CLOCK_CURRENT_VALUE);
+        let data = Bytes::copy_from_slice(&time.timestamp_micros().to_le_bytes());
+        self.client().await?.set_attribute_single(path, data).await.map_err(|e| e.to_string())
+    }
+
+    async fn close(&mut self) {
+        if let Some(mut client) = self.client.take() {
+            let _ = client.close().await;
+        }
+    }
+}
+
+// symbolic segments per member, e.g. "Program:Main.Alarms[3]"
+pub(super) fn encode_tag_path(tag: &str) -> Result<Vec<u8>, String> {
+    let invalid = || format!("invalid tag name {tag:?}");
+    let mut path = vec![];
+    for member in tag.split(".") {
+        let (name, indices) = match member.split_once("[") {
+            None => {(member, None)}
+            Some((name, indices)) => {(name, Some(indices.strip_suffix("]").ok_or_else(invalid)?))}
+        };
+        if name.is_empty() || name.len() > 255 {
+            return Err(invalid());
+        }
+        path.extend_from_slice(&[0x91, name.len() as u8]);
+        path.extend_from_slice(name.as_bytes());
+        if name.len() % 2 == 1 {
+            path.push(0);  // pad to a whole word
+        }
+        for index in indices.iter().flat_map(|indices| indices.split(",")) {
+            let index: u32 = index.trim().parse().map_err(|_| invalid())?;
+            match index {
+                0..=0xFF => {path.extend_from_slice(&[0x28, index as u8])}
+                0x100..=0xFFFF => {
+                    path.extend_from_slice(&[0x29, 0]);
+                    path.extend_from_slice(&(index as u16).to_le_bytes());
+                }
+                _ => {
+                    path.extend_from_slice(&[0x2A, 0]);
+                    path.extend_from_slice(&index.to_le_bytes());
+                }
+            }
+        }
+    }
+    Ok(path)
+}
+
+fn read_tag_request(tag: &str) -> Result<Vec<u8>, String> {
+    let path = encode_tag_path(tag)?;
+    let mut request = vec![READ_TAG, (path.len() / 2) as u8];
+    request.extend_from_slice(&path);
+    request.extend_from_slice(&1u16.to_le_bytes());  // element count
+    Ok(request)
+}
+
+// reply header, type code and value of a successful Read Tag
+fn read_reply_size(value_type: ValueType) -> usize {
+    let value_size = match value_type {
+        ValueType::Bool | ValueType::Sint => {1}
+        ValueType::Int => {2}
+        ValueType::Dint | ValueType::Real => {4}
+        ValueType::Lint => {8}
+    };
+    4 + 2 + value_size
+}
+
+// Number of services per Multiple Service Packet, given the size of each request and its reply.
+// Each service also takes a 2 byte offset, both ways.
+fn packet_counts(sizes: &[(usize, usize)]) -> Vec<usize> {
+    let mut counts = vec![];
+    let (mut count, mut request_size, mut reply_size) = (0, PACKET_REQUEST_OVERHEAD, PACKET_REPLY_OVERHEAD);
+    for (request, reply) in sizes {
+        let full = count == MAX_SERVICES_PER_PACKET
+            || request_size + 2 + request > MAX_MESSAGE_SIZE
+            || reply_size + 2 + reply > MAX_MESSAGE_SIZE;
+        if full && count > 0 {
+            counts.push(count);
+            (count, request_size, reply_size) = (0, PACKET_REQUEST_OVERHEAD, PACKET_REPLY_OVERHEAD);
+        }
+        count += 1;
+        request_size += 2 + request;
+        reply_size += 2 + reply;
+    }
+    if count > 0 {
+        counts.push(count);
+    }
+    counts
+}
+
+fn multiple_service_packet(requests: Vec<&[u8]>) -> Vec<u8> {
+    let mut data = (requests.len() as u16).to_le_bytes().to_vec();
+    let mut offset = 2 + 2 * requests.len();  // offsets count from the start of the service count
+    for request in requests.iter() {
+        data.extend_from_slice(&(offset as u16).to_le_bytes());
+        offset += request.len();
+    }
+    for request in requests {
+        data.extend_from_slice(request);
+    }
+    data
+}
+
+// general status of the whole packet, an embedded service error leaves it to the status of each reply
+fn check_packet_status(status: u8) -> Result<(), String> {
+    match status {
+        0 | PARTIAL_TRANSFER | EMBEDDED_SERVICE_ERROR => {Ok(())}
+        status => {Err(cip_status_text(status))}
+    }
+}
+
+fn check_status(status: u8, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    match status {
+        0 => {Ok(data)}
+        status => {Err(cip_status_text(status))}
+    }
+}
+
+// symbol type word: bit 15 structure, bits 13-14 array dimensions, bit 12 system symbol, atomic type code in the low byte
+fn tag_info(name: String, symbol_type: u16) -> Option<TagInfo> {
+    if symbol_type & 0x1000 != 0 || name.starts_with("__") {
+        return None;
+    }
+    let dimensions = (symbol_type >> 13) & 0x3;
+    let value_type = match symbol_type & 0x80FF {
+        0xC1 => {Some(ValueType::Bool)}
+        0xC2 => {Some(ValueType::Sint)}
+        0xC3 => {Some(ValueType::Int)}
+        0xC4 => {Some(ValueType::Dint)}
+        0xC5 => {Some(ValueType::Lint)}
+        0xCA => {Some(ValueType::Real)}
+        _ => {None}
+    };
+    let type_text = match (value_type, symbol_type & 0x8000 != 0) {
+        (Some(value_type), _) => {value_type.to_string()}
+        (None, true) => {"structure".to_string()}
+        (None, false) => {format!("type 0x{:02X}", symbol_type & 0xFF)}
+    };
+    match dimensions {
+        0 => {Some(TagInfo {name, value_type, type_text})}
+        _ => {Some(TagInfo {name, value_type: None, type_text: format!("{type_text}[{}d]", dimensions)})}
+    }
+}
+
+// general status and data of every embedded reply
+fn parse_multiple_service_reply(data: &[u8]) -> Result<Vec<(u8, Vec<u8>)>, String> {
+    let short = || "short multiple service reply".to_string();
+    let count = u16::from_le_bytes([*data.first().ok_or_else(short)?, *data.get(1).ok_or_else(short)?]) as usize;
+    let mut offsets = vec![];
+    for i in 0..count {
+        let bytes = data.get(2 + i * 2..4 + i * 2).ok_or_else(short)?;
+        offsets.push(u16::from_le_bytes([bytes[0], bytes[1]]) as usize);
+    }
+    let mut replies = vec![];
+    for (i, start) in offsets.iter().enumerate() {
+        let end = offsets.get(i + 1).copied().unwrap_or(data.len());
+        let reply = data.get(*start..end).filter(|reply| reply.len() >= 4).ok_or_else(short)?;
+        // reply service, reserved, general status, extended status size in words
+        let data_start = 4 + reply[3] as usize * 2;
+        replies.push((reply[2], reply.get(data_start..).ok_or_else(short)?.to_vec()));
+    }
+    Ok(replies)
+}
+
+// Read Tag reply data: type code followed by the value
+fn decode_value(data: &[u8]) -> Result<PlcValue, String> {
+    let short = || "short tag value".to_string();
+    let type_code = u16::from_le_bytes([*data.first().ok_or_else(short)?, *data.get(1).ok_or_else(short)?]);
+    let value = &data[2..];
+    let bytes = |length: usize| value.get(..length).ok_or_else(short);
+    match type_code {
+        0xC1 => {Ok(PlcValue::Bool(bytes(1)?[0] != 0))}
+        0xC2 => {Ok(PlcValue::Sint(bytes(1)?[0] as i8))}
+        0xC3 => {Ok(PlcValue::Int(i16::from_le_bytes(bytes(2)?.try_into().unwrap())))}
+        0xC4 => {Ok(PlcValue::Dint(i32::from_le_bytes(bytes(4)?.try_into().unwrap())))}
+        0xC5 => {Ok(PlcValue::Lint(i64::from_le_bytes(bytes(8)?.try_into().unwrap())))}
+        0xCA => {Ok(PlcValue::Real(f32::from_le_bytes(bytes(4)?.try_into().unwrap())))}
+        _ => {Err(format!("unsupported tag type 0x{type_code:04X}"))}
+    }
+}
+
+pub fn cip_status_text(status: u8) -> String {
+    match status {
+        0x01 => {"connection failure".to_string()}
+        0x04 => {"tag doesn't exist (path segment error)".to_string()}
+        0x05 => {"tag doesn't exist (path destination unknown)".to_string()}
+        0x06 => {"partial transfer".to_string()}
+        0x08 => {"service not supported".to_string()}
+        0x0F => {"privilege violation".to_string()}
+        0x10 => {"device state conflict".to_string()}
+        0x13 => {"not enough data".to_string()}
+        0x1E => {"embedded service error".to_string()}
+        _ => {format!("CIP error 0x{status:02X}")}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_symbolic_segments_padded_to_words() {
+        assert_eq!(encode_tag_path("Alarms"), Ok(b"\x91\x06Alarms".to_vec()));
+        assert_eq!(encode_tag_path("Odd"), Ok(b"\x91\x03Odd\x00".to_vec()));
+        assert_eq!(encode_tag_path("Program:Main.Reset"), Ok(b"\x91\x0CProgram:Main\x91\x05Reset\x00".to_vec()));
+    }
+
+    #[test]
+    fn encodes_array_indices_by_size() {
+        assert_eq!(encode_tag_path("Tag[3]"), Ok(b"\x91\x03Tag\x00\x28\x03".to_vec()));
+        assert_eq!(encode_tag_path("Tag[1,2]"), Ok(b"\x91\x03Tag\x00\x28\x01\x28\x02".to_vec()));
+        assert_eq!(encode_tag_path("Tag[300]"), Ok(b"\x91\x03Tag\x00\x29\x00\x2C\x01".to_vec()));
+        assert_eq!(encode_tag_path("Tag[70000]"), Ok(b"\x91\x03Tag\x00\x2A\x00\x70\x11\x01\x00".to_vec()));
+        assert_eq!(encode_tag_path("B_Sys_Alarms[0].Active"), Ok(b"\x91\x0CB_Sys_Alarms\x28\x00\x91\x06Active".to_vec()));
+    }
+
+    #[test]
+    fn rejects_malformed_tag_names() {
+        let too_long = "A".repeat(256);
+        for invalid in ["", "Tag.", "A..B", "Tag[1", "Tag[x]", "Tag[]", "[1]", too_long.as_str()] {
+            assert!(encode_tag_path(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn read_request_is_service_path_size_path_and_count() {
+        assert_eq!(read_tag_request("Alarms"), Ok(b"\x4C\x04\x91\x06Alarms\x01\x00".to_vec()));
+    }
+
+    #[test]
+    fn packet_offsets_count_from_the_service_count() {
+        let packet = multiple_service_packet(vec![&[1, 2, 3], &[4, 5]]);
+        assert_eq!(packet, vec![2, 0, 6, 0, 9, 0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn packets_of_long_names_stay_within_an_unconnected_message() {
+        let names = (0..40).map(|i| format!("Program:Safety_Line_{i:02}.B_Conveyor_Section_{i:02}_Interlock_Status_Word[{i}]")).collect::<Vec<String>>();
+        let requests = names.iter().map(|name| read_tag_request(name).unwrap()).collect::<Vec<Vec<u8>>>();
+        let sizes = requests.iter().map(|request| (request.len(), read_reply_size(ValueType::Lint))).collect::<Vec<(usize, usize)>>();
+        let counts = packet_counts(&sizes);
+        assert_eq!(counts.iter().sum::<usize>(), requests.len());
+        assert!(counts.len() > 2, "{counts:?}");
+        let mut remaining = requests.as_slice();
+        for count in counts {
+            let (chunk, rest) = remaining.split_at(count);
+            remaining = rest;
+            let packet = multiple_service_packet(chunk.iter().map(|request| request.as_slice()).collect());
+            assert!(PACKET_REQUEST_OVERHEAD - 2 + packet.len() <= MAX_MESSAGE_SIZE, "{} bytes", packet.len());
+            let reply_size = PACKET_REPLY_OVERHEAD + chunk.len() * (2 + read_reply_size(ValueType::Lint));
+            assert!(reply_size <= MAX_MESSAGE_SIZE);
+        }
+    }
+
+    #[test]
+    fn short_names_are_capped_by_count() {
+        let counts = packet_counts(&vec![(12, 7); 45]);
+        assert_eq!(counts, vec![MAX_SERVICES_PER_PACKET, MAX_SERVICES_PER_PACKET, 5]);
+    }
+
+    #[test]
+    fn parses_embedded_replies_past_extended_status() {
+        let mut data = vec![2, 0, 6, 0, 13, 0];
+        data.extend_from_slice(&[0xCC, 0, 0x00, 0, 0xC1, 0x00, 0xFF]);  // BOOL true
+        data.extend_from_slice(&[0xCC, 0, 0x04, 1, 0x00, 0x00]);  // path segment error, one extended status word
+        let replies = parse_multiple_service_reply(&data).unwrap();
+        assert_eq!(replies, vec![(0, vec![0xC1, 0x00, 0xFF]), (0x04, vec![])]);
+        assert_eq!(decode_value(&replies[0].1), Ok(PlcValue::Bool(true)));
+    }
+
+    #[test]
+    fn rejects_short_replies() {
+        assert!(parse_multiple_service_reply(&[]).is_err());
+        assert!(parse_multiple_service_reply(&[2, 0, 6, 0]).is_err());  // missing second offset
+        assert!(parse_multiple_service_reply(&[1, 0, 4, 0, 0xCC, 0]).is_err());  // reply header cut off
+        assert!(parse_multiple_service_reply(&[1, 0, 4, 0, 0xCC, 0, 0x04, 2, 0, 0]).is_err());  // extended status cut off
+    }
+
+    #[test]
+    fn packet_status_leaves_embedded_errors_to_each_reply() {
+        assert_eq!(check_packet_status(0), Ok(()));
+        assert_eq!(check_packet_status(EMBEDDED_SERVICE_ERROR), Ok(()));
+        assert_eq!(check_packet_status(PARTIAL_TRANSFER), Ok(()));
+        assert_eq!(check_packet_status(0x08), Err("service not supported".to_string()));
+    }
+
+    #[test]
+    fn decodes_atomic_values() {
+        assert_eq!(decode_value(&[0xC4, 0x00, 0x2A, 0, 0, 0]), Ok(PlcValue::Dint(42)));
+        assert_eq!(decode_value(&[0xC3, 0x00, 0xFF, 0xFF]), Ok(PlcValue::Int(-1)));
+        assert_eq!(decode_value(&[0xCA, 0x00, 0x00, 0x00, 0x80, 0x3F]), Ok(PlcValue::Real(1.0)));
+        assert!(decode_value(&[0xC4, 0x00, 0x2A]).is_err());
+        assert!(decode_value(&[0xA0, 0x02, 0, 0]).is_err());  // structure
+    }
+}
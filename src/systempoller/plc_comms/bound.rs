@@ -0,0 +1,60 @@
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::Pin;
+use rseip::client::{Client, ClientError, Driver};
+use rseip::eip::context::EipContext;
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+
+const EIP_PORT: u16 = 44818;
+
+// rseip's drivers connect from whatever address the OS picks. This one binds the
+// local end first, so traffic leaves through the interface chosen with @source.
+pub struct BoundDriver;
+
+#[derive(Clone, Debug)]
+pub struct BoundEndpoint {
+    target: SocketAddrV4,
+    source: Option<Ipv4Addr>,
+}
+
+impl Driver for BoundDriver {
+    type Endpoint = BoundEndpoint;
+    type Service = EipContext<TcpStream, ClientError>;
+
+    // rseip's BoxFuture, spelled out so the PLC comms don't depend on the GUI crate
+    fn build_service(endpoint: Self::Endpoint) -> Pin<Box<dyn Future<Output = Result<Self::Service, ClientError>> + Send>> {
+        Box::pin(async move {
+            let stream = connect_from(SocketAddr::V4(endpoint.target), endpoint.source).await?;
+            Ok(EipContext::new(stream))
+        })
+    }
+}
+
+pub type BoundClient = Client<BoundDriver>;
+
+pub async fn eip_client(host: &str, source: Option<Ipv4Addr>) -> Result<BoundClient, String> {
+    let target = resolve(host, EIP_PORT).await.map_err(|e| e.to_string())?;
+    Ok(Client::new(BoundEndpoint {target, source}))
+}
+
+pub async fn connect(host: &str, port: u16, source: Option<Ipv4Addr>) -> io::Result<TcpStream> {
+    connect_from(SocketAddr::V4(resolve(host, port).await?), source).await
+}
+
+async fn connect_from(target: SocketAddr, source: Option<Ipv4Addr>) -> io::Result<TcpStream> {
+    let socket = TcpSocket::new_v4()?;
+    if let Some(source) = source {
+        socket.bind(SocketAddr::from((source, 0)))?;
+    }
+    socket.connect(target).await
+}
+
+async fn resolve(host: &str, port: u16) -> io::Result<SocketAddrV4> {
+    lookup_host((host, port)).await?
+        .find_map(|address| match address {
+            SocketAddr::V4(address) => {Some(address)}
+            SocketAddr::V6(_) => {None}
+        })
+        .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("no IPv4 address for {host}")))
+}
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use crate::systempoller::identity::{DeviceIdentity, parse_revision};
+use crate::systempoller::plc_comms::bound::connect;
+use crate::systempoller::plc_comms::{PlcBackend, PlcValue, TagInfo, TagSpec, ValueType};
+
+const READ_COILS: u8 = 0x01;
+const READ_DISCRETE_INPUTS: u8 = 0x02;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+#[cfg(not(feature = "read-only"))]
+const WRITE_SINGLE_COIL: u8 = 0x05;
+#[cfg(not(feature = "read-only"))]
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+#[cfg(not(feature = "read-only"))]
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+const ENCAPSULATED_INTERFACE: u8 = 0x2B;
+const READ_DEVICE_IDENTIFICATION: u8 = 0x0E;
+
+// Modicon style address: 0xxxx coil, 1xxxx discrete input, 3xxxx input register, 4xxxx holding register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModbusAddress {
+    Coil(u16),
+    DiscreteInput(u16),
+    InputRegister(u16),
+    HoldingRegister(u16),
+}
+impl ModbusAddress {
+    pub fn parse(value: &str) -> Result<ModbusAddress, String> {
+        let invalid = || format!("invalid modbus address {value:?}, expected e.g. 00001, 10001, 30001 or 40001");
+        if !value.chars().all(|c| c.is_ascii_digit()) || value.len() > 6 {
+            return Err(invalid());
+        }
+        let value = format!("{value:0>5}");
+        let (kind, offset) = value.split_at(1);
+        let offset = offset.parse::<u32>().map_err(|_| invalid())?.checked_sub(1)
+            .and_then(|offset| u16::try_from(offset).ok()).ok_or_else(invalid)?;
+        match kind {
+            "0" => {Ok(ModbusAddress::Coil(offset))}
+            "1" => {Ok(ModbusAddress::DiscreteInput(offset))}
+            "3" => {Ok(ModbusAddress::InputRegister(offset))}
+            "4" => {Ok(ModbusAddress::HoldingRegister(offset))}
+            _ => {Err(invalid())}
+        }
+    }
+}
+impl fmt::Display for ModbusAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, offset) = match self {
+            ModbusAddress::Coil(offset) => {(0, offset)}
+            ModbusAddress::DiscreteInput(offset) => {(1, offset)}
+            ModbusAddress::InputRegister(offset) => {(3, offset)}
+            ModbusAddress::HoldingRegister(offset) => {(4, offset)}
+        };
+        write!(f, "{}{:04}", kind, *offset as u32 + 1)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModbusConfig {
+    pub port: u16,
+    pub unit_id: u8,
+    pub alarm: ModbusAddress,
+    pub resets: Vec<ModbusAddress>,  // coils
+}
+
+// Modbus TCP safety panel, registers count as set when non-zero
+pub struct ModbusBackend {
+    ip_address: String,
+    config: ModbusConfig,
+    source: Option<Ipv4Addr>,
+    stream: Option<TcpStream>,
+    transaction_id: u16,
+}
+impl ModbusBackend {
+    pub fn new(ip_address: &str, config: &ModbusConfig, source: Option<Ipv4Addr>) -> Self {
+        ModbusBackend {ip_address: ip_address.to_string(), config: config.clone(), source, stream: None, transaction_id: 0}
+    }
+
+    async fn stream(&mut self) -> Result<&mut TcpStream, String> {
+        if self.stream.is_none() {
+            let stream = connect(&self.ip_address, self.config.port, self.source).await.map_err(|e| e.to_string())?;
+            self.stream = Some(stream);
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    // sends a PDU and returns the response PDU, exceptions become errors
+    async fn request(&mut self, pdu: &[u8]) -> Result<Vec<u8>, String> {
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        let frame = mbap_frame(self.transaction_id, self.config.unit_id, pdu);
+
+        let transaction_id = self.transaction_id;
+        let stream = self.stream().await?;
+        let result = exchange(stream, &frame, transaction_id).await;
+        if result.is_err() {
+            self.stream = None;  // reconnect on next request
+        }
+        check_response(pdu[0], result?)
+    }
+
+    async fn read_registers(&mut self, address: ModbusAddress, count: u16) -> Result<Vec<u16>, String> {
+        let (function, offset) = match address {
+            ModbusAddress::InputRegister(offset) => {(READ_INPUT_REGISTERS, offset)}
+            ModbusAddress::HoldingRegister(offset) => {(READ_HOLDING_REGISTERS, offset)}
+            _ => {return Err(format!("{address} is not a register"))}
+        };
+        let response = self.request(&read_request(function, offset, count)).await?;
+        parse_registers(&response, count)
+    }
+
+    async fn read_value(&mut self, address: ModbusAddress, value_type: ValueType) -> Result<PlcValue, String> {
+        let words = |registers: Vec<u16>| join_words(&registers);
+        match value_type {
+            ValueType::Bool => {Ok(PlcValue::Bool(self.read_bit(address).await?))}
+            ValueType::Sint => {Ok(PlcValue::Sint(self.read_registers(address, 1).await?[0] as i8))}
+            ValueType::Int => {Ok(PlcValue::Int(self.read_registers(address, 1).await?[0] as i16))}
+            ValueType::Dint => {Ok(PlcValue::Dint(words(self.read_registers(address, 2).await?) as i32))}
+            ValueType::Lint => {Ok(PlcValue::Lint(words(self.read_registers(address, 4).await?) as i64))}
+            ValueType::Real => {Ok(PlcValue::Real(f32::from_bits(words(self.read_registers(address, 2).await?) as u32)))}
+        }
+    }
+
+    pub async fn read_bit(&mut self, address: ModbusAddress) -> Result<bool, String> {
+        let (function, offset) = match address {
+            ModbusAddress::Coil(offset) => {(READ_COILS, offset)}
+            ModbusAddress::DiscreteInput(offset) => {(READ_DISCRETE_INPUTS, offset)}
+            ModbusAddress::InputRegister(offset) => {(READ_INPUT_REGISTERS, offset)}
+            ModbusAddress::HoldingRegister(offset) => {(READ_HOLDING_REGISTERS, offset)}
+        };
+        let response = self.request(&read_request(function, offset, 1)).await?;
+        parse_bit(function, &response)
+    }
+
+    // Read Device Identification, basic objects: vendor name, product code, revision
+    async fn device_identification(&mut self) -> Result<Vec<String>, String> {
+        let response = self.request(&[ENCAPSULATED_INTERFACE, READ_DEVICE_IDENTIFICATION, 0x01, 0x00]).await?;
+        parse_device_identification(&response)
+    }
+}
+
+// MBAP header: transaction id, protocol id 0, length of unit id and PDU, unit id
+fn mbap_frame(transaction_id: u16, unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+    let mut frame = vec![];
+    frame.extend_from_slice(&transaction_id.to_be_bytes());
+    frame.extend_from_slice(&0u16.to_be_bytes());  // protocol id
+    frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    frame.push(unit_id);
+    frame.extend_from_slice(pdu);
+    frame
+}
+
+// response PDU to a request with the given function code, exceptions become errors
+fn check_response(function: u8, response: Vec<u8>) -> Result<Vec<u8>, String> {
+    match response.first() {
+        Some(code) if *code == function | 0x80 => {Err(exception_text(response.get(1).copied().unwrap_or(0)))}
+        Some(code) if *code == function => {Ok(response)}
+        _ => {Err("unexpected modbus response".to_string())}
+    }
+}
+
+fn exception_text(code: u8) -> String {
+    match code {
+        0x01 => {"modbus exception 1, illegal function".to_string()}
+        0x02 => {"modbus exception 2, illegal data address".to_string()}
+        0x03 => {"modbus exception 3, illegal data value".to_string()}
+        0x04 => {"modbus exception 4, server device failure".to_string()}
+        0x06 => {"modbus exception 6, server device busy".to_string()}
+        0x0B => {"modbus exception 11, gateway target device failed to respond".to_string()}
+        code => {format!("modbus exception {code}")}
+    }
+}
+
+fn read_request(function: u8, offset: u16, count: u16) -> Vec<u8> {
+    let mut pdu = vec![function];
+    pdu.extend_from_slice(&offset.to_be_bytes());
+    pdu.extend_from_slice(&count.to_be_bytes());
+    pdu
+}
+
+// function code, byte count, big endian registers
+fn parse_registers(response: &[u8], count: u16) -> Result<Vec<u16>, String> {
+    let data = response.get(2..2 + count as usize * 2).ok_or("short modbus response")?;
+    Ok(data.chunks(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect())
+}
+
+// coils and inputs are packed bits, a register counts as set when non-zero
+fn parse_bit(function: u8, response: &[u8]) -> Result<bool, String> {
+    let data = response.get(2..).filter(|data| !data.is_empty()).ok_or("short modbus response")?;
+    match function {
+        READ_COILS | READ_DISCRETE_INPUTS => {Ok(data[0] & 0x01 != 0)}
+        _ => {Ok(data.iter().any(|byte| *byte != 0))}
+    }
+}
+
+// multi register values are big endian, high word first
+fn join_words(registers: &[u16]) -> u64 {
+    registers.iter().fold(0u64, |value, word| (value << 16) | *word as u64)
+}
+
+// function, MEI type, read code, conformity, more follows, next object, object count, objects
+fn parse_device_identification(response: &[u8]) -> Result<Vec<String>, String> {
+    let count = *response.get(6).ok_or("short modbus response")? as usize;
+    let mut objects = vec![];
+    let mut i = 7;
+    for _ in 0..count {
+        let length = *response.get(i + 1).ok_or("short modbus response")? as usize;
+        let value = response.get(i + 2..i + 2 + length).ok_or("short modbus response")?;
+        objects.push(String::from_utf8_lossy(value).to_string());
+        i += 2 + length;
+    }
+    Ok(objects)
+}
+
+async fn exchange(stream: &mut TcpStream, frame: &[u8], transaction_id: u16) -> Result<Vec<u8>, String> {
+    stream.write_all(frame).await.map_err(|e| e.to_string())?;
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header).await.map_err(|e| e.to_string())?;
+    if u16::from_be_bytes([header[0], header[1]]) != transaction_id {
+        return Err("modbus transaction id mismatch".to_string());
+    }
+    let length = (u16::from_be_bytes([header[4], header[5]]) as usize).saturating_sub(1);  // minus unit id
+    let mut response = vec![0u8; length];
+    stream.read_exact(&mut response).await.map_err(|e| e.to_string())?;
+    Ok(response)
+}
+
+impl PlcBackend for ModbusBackend {
+    fn alarm_summary_tag(&self) -> String {
+        self.config.alarm.to_string()
+    }
+
+    fn connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // Modbus has no batching across tables, so one request per tag on the same connection
+    async fn read_tags(&mut self, tags: &[TagSpec]) -> Result<HashMap<String, Result<PlcValue, String>>, String> {
+        self.stream().await?;  // a panel that can't be reached fails the read, not each tag
+        let mut values = HashMap::new();
+        for tag in tags {
+            let value = match ModbusAddress::parse(&tag.name) {
+                Ok(address) => {self.read_value(address, tag.value_type).await}
+                Err(error_message) => {Err(error_message)}
+            };
+            match value {
+                Err(error_message) if self.stream.is_none() => {return Err(error_message)}  // connection lost
+                value => {values.insert(tag.name.to_string(), value);}
+            }
+        }
+        Ok(values)
+    }
+
+    fn reset_tags(&self) -> Vec<String> {
+        self.config.resets.iter().map(|address| address.to_string()).collect()
+    }
+
+    #[cfg(not(feature = "read-only"))]
+    async fn write_bool(&mut self, tag: &str, value: bool) -> Result<(), String> {
+        let ModbusAddress::Coil(offset) = ModbusAddress::parse(tag)? else {
+            return Err(format!("{tag} is not a coil"));
+        };
+        let mut pdu = vec![WRITE_SINGLE_COIL];
+        pdu.extend_from_slice(&offset.to_be_bytes());
+        pdu.extend_from_slice(&(if value {0xFF00u16} else {0x0000}).to_be_bytes());
+        self.request(&pdu).await.map(|_| ())
+    }
+
+    // registers high word first, like read_value
+    #[cfg(not(feature = "read-only"))]
+    async fn write_value(&mut self, tag: &str, value: PlcValue) -> Result<(), String> {
+        let words = |value: u64, count: usize| (0..count).rev().map(|i| (value >> (16 * i)) as u16).collect::<Vec<u16>>();
+        let registers = match value {
+            PlcValue::Bool(value) => {return self.write_bool(tag, value).await}
+            PlcValue::Sint(value) => {vec![value as u16]}
+            PlcValue::Int(value) => {vec![value as u16]}
+            PlcValue::Dint(value) => {words(value as u32 as u64, 2)}
+            PlcValue::Lint(value) => {words(value as u64, 4)}
+            PlcValue::Real(value) => {words(value.to_bits() as u64, 2)}
+        };
+        let ModbusAddress::HoldingRegister(offset) = ModbusAddress::parse(tag)? else {
+            return Err(format!("{tag} is not a holding register"));
+        };
+        let mut pdu = match registers.len() {
+            1 => {vec![WRITE_SINGLE_REGISTER]}
+            _ => {vec![WRITE_MULTIPLE_REGISTERS]}
+        };
+        pdu.extend_from_slice(&offset.to_be_bytes());
+        if registers.len() > 1 {
+            pdu.extend_from_slice(&(registers.len() as u16).to_be_bytes());
+            pdu.push(registers.len() as u8 * 2);
+        }
+        for register in registers {
+            pdu.extend_from_slice(&register.to_be_bytes());
+        }
+        self.request(&pdu).await.map(|_| ())
+    }
+
+    async fn list_tags(&mut self) -> Result<Vec<TagInfo>, String> {
+        Err("Modbus has no tag names to browse, enter an address like 40001".to_string())
+    }
+
+    // Modbus has no CIP identity or controller mode, fill in what Read Device Identification gives
+    async fn identity(&mut self) -> Option<DeviceIdentity> {
+        let objects = self.device_identification().await.ok()?;
+        Some(DeviceIdentity {
+            vendor_id: 0,
+            device_type: 0,
+            product_code: 0,
+            revision: objects.get(2).and_then(|revision| parse_revision(revision.trim_start_matches(['v', 'V']))).unwrap_or((0, 0)),
+            status: 0,
+            serial_number: 0,
+            product_name: format!("{} {}", objects.first().map_or("", |name| name.as_str()), objects.get(1).map_or("", |code| code.as_str())).trim().to_string(),
+        })
+    }
+
+    // Modbus has no standard clock object
+    async fn wall_clock(&mut self) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    #[cfg(not(feature = "read-only"))]
+    async fn set_wall_clock(&mut self, _time: DateTime<Utc>) -> Result<(), String> {
+        Err("setting the clock is not supported over modbus".to_string())
+    }
+
+    async fn close(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = stream.shutdown().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parses_modicon_addresses() {
+        assert_eq!(ModbusAddress::parse("00001"), Ok(ModbusAddress::Coil(0)));
+        assert_eq!(ModbusAddress::parse("1"), Ok(ModbusAddress::Coil(0)));
+        assert_eq!(ModbusAddress::parse("10001"), Ok(ModbusAddress::DiscreteInput(0)));
+        assert_eq!(ModbusAddress::parse("30010"), Ok(ModbusAddress::InputRegister(9)));
+        assert_eq!(ModbusAddress::parse("40001"), Ok(ModbusAddress::HoldingRegister(0)));
+        assert_eq!(ModbusAddress::parse("465536"), Ok(ModbusAddress::HoldingRegister(65535)));
+        for invalid in ["", "00000", "20001", "4000a", "465537", "4000001"] {
+            assert!(ModbusAddress::parse(invalid).is_err(), "{invalid:?}");
+        }
+        assert_eq!(ModbusAddress::HoldingRegister(9).to_string(), "40010");
+    }
+
+    #[test]
+    fn frames_requests_with_mbap_header() {
+        let pdu = read_request(READ_HOLDING_REGISTERS, 0, 2);
+        assert_eq!(mbap_frame(0x1234, 7, &pdu), vec![0x12, 0x34, 0, 0, 0, 6, 7, 0x03, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn exception_responses_become_errors() {
+        assert_eq!(check_response(0x03, vec![0x83, 0x02]), Err("modbus exception 2, illegal data address".to_string()));
+        assert_eq!(check_response(0x03, vec![0x04, 0x02, 0, 0]), Err("unexpected modbus response".to_string()));
+        assert_eq!(check_response(0x03, vec![]), Err("unexpected modbus response".to_string()));
+        assert_eq!(check_response(0x03, vec![0x03, 0x02, 0, 1]), Ok(vec![0x03, 0x02, 0, 1]));
+    }
+
+    #[test]
+    fn parses_responses() {
+        assert_eq!(parse_registers(&[0x03, 4, 0x00, 0x01, 0xFF, 0xFE], 2), Ok(vec![0x0001, 0xFFFE]));
+        assert!(parse_registers(&[0x03, 2, 0x00, 0x01], 2).is_err());
+        assert_eq!(parse_bit(READ_COILS, &[READ_COILS, 1, 0x01]), Ok(true));
+        assert_eq!(parse_bit(READ_DISCRETE_INPUTS, &[READ_DISCRETE_INPUTS, 1, 0x02]), Ok(false));
+        assert_eq!(parse_bit(READ_HOLDING_REGISTERS, &[READ_HOLDING_REGISTERS, 2, 0x01, 0x00]), Ok(true));
+        assert_eq!(join_words(&[0x0001, 0x0002]), 0x0001_0002);
+        let identification = [0x2B, 0x0E, 0x01, 0x01, 0x00, 0x00, 0x02, 0x00, 3, b'A', b'B', b'C', 0x01, 2, b'X', b'1'];
+        assert_eq!(parse_device_identification(&identification), Ok(vec!["ABC".to_string(), "X1".to_string()]));
+        assert!(parse_device_identification(&identification[..10]).is_err());
+    }
+
+    // minimal Modbus TCP server: coil 00001 set, holding registers 40001-40002 = 1, 2, everything else an exception
+    async fn local_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0u8; 7];
+            while stream.read_exact(&mut header).await.is_ok() {
+                let mut pdu = vec![0u8; u16::from_be_bytes([header[4], header[5]]) as usize - 1];
+                stream.read_exact(&mut pdu).await.unwrap();
+                let response = match (pdu[0], u16::from_be_bytes([pdu[1], pdu[2]])) {
+                    (READ_COILS, 0) => {vec![READ_COILS, 1, 0x01]}
+                    (READ_HOLDING_REGISTERS, 0) => {vec![READ_HOLDING_REGISTERS, 4, 0x00, 0x01, 0x00, 0x02]}
+                    (function, _) => {vec![function | 0x80, 0x02]}
+                };
+                let frame = mbap_frame(u16::from_be_bytes([header[0], header[1]]), header[6], &response);
+                stream.write_all(&frame).await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn reads_tags_from_a_local_server() {
+        let port = local_server().await;
+        let config = ModbusConfig {port, unit_id: 1, alarm: ModbusAddress::Coil(0), resets: vec![]};
+        let mut backend = ModbusBackend::new("127.0.0.1", &config, None);
+        let tags = [
+            TagSpec {name: "00001".to_string(), value_type: ValueType::Bool},
+            TagSpec {name: "40001".to_string(), value_type: ValueType::Dint},
+            TagSpec {name: "30001".to_string(), value_type: ValueType::Int},
+        ];
+        let values = backend.read_tags(&tags).await.unwrap();
+        assert_eq!(values["00001"], Ok(PlcValue::Bool(true)));
+        assert_eq!(values["40001"], Ok(PlcValue::Dint(0x0001_0002)));
+        assert_eq!(values["30001"], Err("modbus exception 2, illegal data address".to_string()));
+        backend.close().await;
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_fails_the_read() {
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();  // closed again
+        let config = ModbusConfig {port, unit_id: 1, alarm: ModbusAddress::Coil(0), resets: vec![]};
+        let mut backend = ModbusBackend::new("127.0.0.1", &config, None);
+        let tags = [TagSpec {name: "bad".to_string(), value_type: ValueType::Bool}];
+        assert!(backend.read_tags(&tags).await.is_err());
+        assert!(!backend.connected());
+    }
+}
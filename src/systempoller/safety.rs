@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use chrono::{DateTime, Local};
+use crate::systempoller::plc_comms::{PlcValue, TagSpec, ValueType};
+
+// GuardLogix safety state, not read over CIP but copied by the PLC logic from GSV SafetyController into
+// B_<sys>_SafetyStatus (INT), B_<sys>_SafetySignatureID (DINT) and, optionally, the signature's
+// date and time into B_<sys>_SafetySignatureTime (LINT microseconds since 1970)
+#[derive(Clone, Debug, PartialEq)]
+pub struct SafetyState {
+    pub status: u16,
+    pub signature: Option<u32>,  // None when the program has no safety signature
+    pub signature_time: Option<i64>,  // None if the logic doesn't copy it
+}
+impl SafetyState {
+    pub fn tag_specs(system_name: &str) -> Vec<TagSpec> {
+        vec![
+            TagSpec {name: status_tag(system_name), value_type: ValueType::Int},
+            TagSpec {name: signature_tag(system_name), value_type: ValueType::Dint},
+            TagSpec {name: signature_time_tag(system_name), value_type: ValueType::Lint},
+        ]
+    }
+
+    pub fn read(system_name: &str, tags: &BTreeMap<String, PlcValue>) -> Option<SafetyState> {
+        let status = tags.get(&status_tag(system_name))?.as_i64()? as u16;
+        let signature = tags.get(&signature_tag(system_name))?.as_i64()? as u32;
+        let signature_time = tags.get(&signature_time_tag(system_name)).and_then(|value| value.as_i64()).filter(|time| *time != 0);
+        Some(SafetyState {status, signature: Some(signature).filter(|signature| *signature != 0), signature_time})
+    }
+
+    pub fn task_ok(&self) -> bool {
+        self.status & 0x7FFF == 0
+    }
+
+    pub fn locked(&self) -> bool {
+        self.status & 0x8000 != 0
+    }
+
+    pub fn task_status_text(&self) -> String {
+        match self.status & 0x7FFF {
+            0 => {"safety task OK".to_string()}
+            1 => {"safety task inoperable".to_string()}
+            2 => {"partner missing".to_string()}
+            3 => {"partner unavailable".to_string()}
+            4 => {"hardware incompatible".to_string()}
+            5 => {"partner firmware incompatible".to_string()}
+            status => {format!("safety status {status}")}
+        }
+    }
+}
+impl fmt::Display for SafetyState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}, ", self.task_status_text(), if self.locked() {"locked"} else {"unlocked"})?;
+        match (self.signature, self.signature_time) {
+            (None, _) => {write!(f, "no signature")}
+            (Some(signature), None) => {write!(f, "signature {signature:08X}, time not read")}
+            (Some(signature), Some(time)) => {write!(f, "signature {signature:08X} {}", time_text(time))}
+        }
+    }
+}
+
+// The signature ID alone can stay the same across a program change, the time tells them apart
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpectedSignature {
+    pub id: u32,
+    pub time: Option<i64>,
+}
+impl ExpectedSignature {
+    pub fn parse(id: &str, time: Option<&str>) -> Result<Self, String> {
+        let id = u32::from_str_radix(id.trim_start_matches("0x"), 16)
+            .map_err(|_| format!("invalid safety signature {id:?}, expected hex"))?;
+        let time = time.map(|time| time.parse::<i64>().map_err(|_| format!("invalid safety signature time {time:?}, expected microseconds since 1970")))
+            .transpose()?;
+        Ok(ExpectedSignature {id, time})
+    }
+
+    pub fn matches(&self, state: &SafetyState) -> bool {
+        state.signature == Some(self.id) && self.time.is_none_or(|time| state.signature_time == Some(time))
+    }
+}
+impl fmt::Display for ExpectedSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.time {
+            None => {write!(f, "{:08X}", self.id)}
+            Some(time) => {write!(f, "{:08X} {}", self.id, time_text(time))}
+        }
+    }
+}
+
+// local time, and the raw value to copy into the config
+fn time_text(time: i64) -> String {
+    match DateTime::from_timestamp_micros(time) {
+        None => {format!("({time})")}
+        Some(date_time) => {format!("{} ({time})", date_time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"))}
+    }
+}
+
+fn status_tag(system_name: &str) -> String {
+    format!("B_{system_name}_SafetyStatus")
+}
+
+fn signature_tag(system_name: &str) -> String {
+    format!("B_{system_name}_SafetySignatureID")
+}
+
+fn signature_time_tag(system_name: &str) -> String {
+    format!("B_{system_name}_SafetySignatureTime")
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, UdpSocket};
+use crate::systempoller::bootp::local_address_towards;
+
+// This computer's own connection to the PSS network, so an unplugged cable
+// isn't shown as every system failing at once
+#[derive(Clone, Debug, Default)]
+pub struct SelfCheck {
+    pub local_address: Option<Ipv4Addr>,  // None if there is no route or the source address is gone
+    pub references: Vec<(Ipv4Addr, bool)>,  // reference host, responding
+    pub hosts_responding: usize,  // configured plant hosts answering pings
+}
+impl SelfCheck {
+    // target is any plant address, used to find the interface when no source is set
+    pub async fn run(source: Option<Ipv4Addr>, target: Option<Ipv4Addr>, references: &[Ipv4Addr], ping_results: &HashMap<String, bool>) -> Self {
+        let route_target = references.first().copied().or(target);
+        let local_address = match (source, route_target) {
+            (Some(source), _) => {UdpSocket::bind((source, 0)).ok().map(|_| source)}  // adapter unplugged or removed
+            (None, Some(target)) => {local_address_towards(target).await.filter(|address| !address.is_loopback())}
+            (None, None) => {None}
+        };
+        let references = references.iter()
+            .map(|address| (*address, ping_results.get(&address.to_string()).copied().unwrap_or(false)))
+            .collect::<Vec<(Ipv4Addr, bool)>>();
+        let hosts_responding = ping_results.iter()
+            .filter(|(address, responding)| **responding && !references.iter().any(|(reference, _)| reference.to_string() == **address))
+            .count();
+        SelfCheck {local_address, references, hosts_responding}
+    }
+
+    // Any answering plant host proves the connection, so an outage that takes the gateway down
+    // doesn't hide the plant. A route alone proves nothing, it may lead out through another adapter,
+    // so without reference hosts a silent plant is shown as it is.
+    pub fn connected(&self) -> bool {
+        if self.hosts_responding > 0 {
+            return true;
+        }
+        self.local_address.is_some() && (self.references.is_empty() || self.references.iter().any(|(_, responding)| *responding))
+    }
+
+    pub fn info(&self) -> String {
+        let interface = match self.local_address {
+            None => {"This computer has no address on the PSS network".to_string()}
+            Some(address) => {format!("This computer: {address}")}
+        };
+        let references = self.references.iter()
+            .map(|(address, responding)| format!("{address} {}", match responding {
+                true => {"responding"}
+                false => {"not responding"}
+            }))
+            .collect::<Vec<String>>();
+        match references.is_empty() {
+            true => {format!("{interface}, no plant host is responding")}
+            false => {format!("{interface}, no plant host is responding, reference hosts: {}", references.join(", "))}
+        }
+    }
+}
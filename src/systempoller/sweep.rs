@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::task::JoinSet;
+use crate::systempoller::multipinger::Multipinger;
+use crate::systempoller::importer::{system_name, is_eth};
+use crate::systempoller::discovery::parse_cidr;
+
+// Pings every address in the subnets and proposes a config for the ones answering,
+// grouped into systems with the same naming rules the importer uses.
+pub async fn propose_config(subnets: Vec<String>, source: Option<Ipv4Addr>) -> Result<String, String> {
+    let mut addresses = vec![];
+    for subnet in subnets.iter().filter(|subnet| !subnet.trim().is_empty()) {
+        addresses.extend(parse_cidr(subnet)?);
+    }
+    if addresses.is_empty() {
+        return Err("No addresses to sweep".to_string());
+    }
+
+    let pinger = Multipinger::new(addresses.iter().map(|address| address.to_string()).collect(), source);
+    let responding = pinger.ping_all().await.into_iter()
+        .filter(|(_, responding)| *responding)
+        .filter_map(|(address, _)| address.parse::<Ipv4Addr>().ok())
+        .collect::<Vec<Ipv4Addr>>();
+
+    let mut set = JoinSet::new();
+    for address in responding {
+        set.spawn_blocking(move || (address, reverse_lookup(address)));
+    }
+    // system name -> (hostname, address), eths first as in a hand written config
+    let mut systems: BTreeMap<String, Vec<(bool, String, Ipv4Addr)>> = BTreeMap::new();
+    while let Some(res) = set.join_next().await {
+        if let Ok((address, hostname)) = res {
+            let hostname = hostname.unwrap_or(format!("UNNAMED_{}", address.to_string().replace(".", "-")));
+            systems.entry(system_name(&hostname)).or_default().push((!is_eth(&hostname), hostname, address));
+        }
+    }
+
+    let mut lines = vec![format!("# generated from a sweep of {}", subnets.join(" "))];
+    for (system_name, mut hosts) in systems {
+        hosts.sort();
+        lines.push(format!("# system {system_name}"));
+        lines.extend(hosts.into_iter().map(|(_, hostname, address)| format!("{hostname}, {address}")));
+    }
+    Ok(lines.join("\n"))
+}
+
+// short hostname without domain, as used in the config
+fn reverse_lookup(address: Ipv4Addr) -> Option<String> {
+    let name = dns_lookup::lookup_addr(&IpAddr::V4(address)).ok()?;
+    let short_name = name.split(".").next()?.to_string();
+    match short_name.is_empty() || short_name.parse::<u8>().is_ok() {  // resolver echoing the address back
+        true => {None}
+        false => {Some(short_name)}
+    }
+}